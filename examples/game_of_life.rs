@@ -1,17 +1,13 @@
 use std::process::exit;
 
 use term_gfx::{
-    app::{AppError, AppStartupConfig, Scene},
+    app::{AppStartupConfig, Scene, SceneCommand, SceneError},
+    time::{Time, Timestep},
     profile, Color, Renderer,
 };
 
 const BORDER_COLOR: u8 = 127;
 
-fn error_handler(err: AppError) {
-    eprintln!("Got an error: {:?}", err);
-    exit(-1);
-}
-
 struct GameOfLifeScene {
     cells: Vec<bool>, 
     width: usize,
@@ -19,7 +15,7 @@ struct GameOfLifeScene {
 }
 
 impl GameOfLifeScene {
-    fn neighbours_helper(&self, old: &Vec<bool>, x: i32, y: i32) -> u32 {
+    fn neighbours_helper(&self, old: &[bool], x: i32, y: i32) -> u32 {
         if y < 0 || x < 0 || y as usize >= self.height || x as usize >= self.width {
             return 0;
         }
@@ -31,7 +27,7 @@ impl GameOfLifeScene {
         }
     }
 
-    fn neighbours(&self, old: &Vec<bool>, x: i32, y: i32) -> u32 {
+    fn neighbours(&self, old: &[bool], x: i32, y: i32) -> u32 {
         let mut erg = 0;
         for i in -1..=1 {
             for j in -1..=1 {
@@ -62,9 +58,11 @@ impl GameOfLifeScene {
 }
 
 impl Scene for GameOfLifeScene {
-    fn detach(&mut self) {}
+    fn detach(&mut self) -> Result<(), SceneError> {
+        Ok(())
+    }
 
-    fn attach(&mut self, app_info: &term_gfx::app::AppInfo) {
+    fn attach(&mut self, app_info: &term_gfx::app::AppInfo) -> Result<(), SceneError> {
         profile!();
         let (w, h) = app_info.renderer.as_ref().borrow().screen_size();
         let w = (w - 2) as usize;
@@ -85,9 +83,10 @@ impl Scene for GameOfLifeScene {
         self.cells[5 * self.width + 5] = true;
         self.cells[5 * self.width + 4] = true;
         self.cells[4 * self.width + 3] = true;
+        Ok(())
     }
 
-    fn update(&mut self, renderer: &mut Renderer) {
+    fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
         let (w, h) = renderer.screen_size();
 
         {
@@ -103,8 +102,6 @@ impl Scene for GameOfLifeScene {
             }
         }
 
-        self.cycle();
-
         {
             profile!("Draw Grid");
             for y in 1..(h - 1) {
@@ -118,17 +115,34 @@ impl Scene for GameOfLifeScene {
                 }
             }
         }
+
+        Ok(SceneCommand::None)
     }
 
-    fn event(&mut self, event: &term_gfx::event::Event) {
-        
+    fn fixed_update(&mut self, _time: &Time) -> Result<(), SceneError> {
+        self.cycle();
+        Ok(())
+    }
+
+    fn event(&mut self, _event: &term_gfx::event::Event) -> Result<SceneCommand, SceneError> {
+        Ok(SceneCommand::None)
     }
 }
 
 fn main() {
-    let cfg = AppStartupConfig { fps: 60 };
+    let cfg = AppStartupConfig {
+        fps: 60,
+        timestep: Timestep::fixed_hz(15),
+        ..Default::default()
+    };
 
     let scene = Box::new(GameOfLifeScene { cells: Vec::new(), width: 0, height: 0 });
 
-    term_gfx::run(scene, cfg, error_handler);
+    match term_gfx::run(scene, cfg) {
+        Ok(status) => exit(status.code()),
+        Err(err) => {
+            eprintln!("Got an error: {:?}", err);
+            exit(-1);
+        }
+    }
 }
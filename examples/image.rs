@@ -1,19 +1,18 @@
 use std::process::exit;
 
 use term_gfx::{
-    app::{AppError, AppStartupConfig, Scene},
+    app::{AppStartupConfig, Scene, SceneCommand, SceneError},
     event::Event,
+    color::ColorDepth,
+    framebuffer::{Dithering, RenderMode},
+    time::Time,
     Framebuffer, Renderer,
 };
 
-fn error_handler(err: AppError) {
-    eprintln!("Got an error: {:?}", err);
-    exit(-1);
-}
-
 struct ExampleScene {
     img_org: Option<Framebuffer>,
     img: Option<Framebuffer>,
+    depth: ColorDepth,
 }
 
 impl ExampleScene {
@@ -23,40 +22,71 @@ impl ExampleScene {
         ExampleScene {
             img: None,
             img_org: Some(img),
+            depth: ColorDepth::TrueColor,
+        }
+    }
+
+    fn resize_image(&mut self, w: usize, h: usize) {
+        if let Some(i) = &self.img_org {
+            let resized = Framebuffer::new_resized(i, w, h);
+            self.img = Some(Framebuffer::new_dithered(
+                &resized,
+                self.depth,
+                Dithering::FloydSteinberg,
+            ));
         }
     }
 }
 
 impl Scene for ExampleScene {
-    fn attach(&mut self, _app_info: &term_gfx::app::AppInfo) {
-        if let Some(i) = &self.img_org {
-            let (w, h) = _app_info.renderer.as_ref().borrow().screen_size();
+    fn attach(&mut self, app_info: &term_gfx::app::AppInfo) -> Result<(), SceneError> {
+        let (w, h) = app_info.renderer.as_ref().borrow().screen_size();
+        self.depth = app_info.renderer.as_ref().borrow().color_depth();
 
-            self.img = Some(Framebuffer::new_resized(i, w as usize, h as usize));
-        }
+        self.resize_image(w as usize, h as usize);
+        Ok(())
     }
 
-    fn detach(&mut self) {}
+    fn detach(&mut self) -> Result<(), SceneError> {
+        Ok(())
+    }
 
-    fn update(&mut self, renderer: &mut Renderer) {
+    fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
         if let Some(i) = &self.img {
             renderer.draw_framebuffer(0, 0, i);
         }
+        Ok(SceneCommand::None)
     }
 
-    fn event(&mut self, event: &term_gfx::event::Event) {
+    fn event(&mut self, event: &term_gfx::event::Event) -> Result<SceneCommand, SceneError> {
         if let Event::Resize(w, h) = event {
-            if let Some(i) = &self.img_org {
-                self.img = Some(Framebuffer::new_resized(i, *w as usize, *h as usize));
-            }
+            self.resize_image(*w as usize, *h as usize);
         }
+        Ok(SceneCommand::None)
     }
 }
 
 fn main() {
-    let cfg = AppStartupConfig { fps: 30 };
+    let render_mode = match std::env::args().nth(1).as_deref() {
+        Some("full") => RenderMode::Full,
+        Some("quadrant") => RenderMode::Quadrant,
+        Some("sextant") => RenderMode::Sextant,
+        _ => RenderMode::HalfBlock,
+    };
+
+    let cfg = AppStartupConfig {
+        fps: 30,
+        render_mode,
+        ..Default::default()
+    };
 
     let scene = Box::new(ExampleScene::new());
 
-    term_gfx::run(scene, cfg, error_handler);
+    match term_gfx::run(scene, cfg) {
+        Ok(status) => exit(status.code()),
+        Err(err) => {
+            eprintln!("Got an error: {:?}", err);
+            exit(-1);
+        }
+    }
 }
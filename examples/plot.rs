@@ -0,0 +1,78 @@
+use std::process::exit;
+
+use term_gfx::{
+    app::{AppStartupConfig, Scene, SceneCommand, SceneError},
+    event::Event,
+    framebuffer::RenderMode,
+    time::Time,
+    Color, Renderer,
+};
+
+struct PlotScene {
+    phase: f64,
+}
+
+impl PlotScene {
+    fn plot<F>(&self, renderer: &mut Renderer, color: Color, f: F)
+    where
+        F: Fn(f64) -> f64,
+    {
+        let (w, h) = renderer.screen_size();
+
+        let to_screen = |x: i64| {
+            let y = f(x as f64 / w as f64 * 4.0 * std::f64::consts::PI + self.phase);
+            ((1.0 - y) * (h - 1) as f64 / 2.0) as i64
+        };
+
+        let mut last = to_screen(0);
+        for x in 1..w {
+            let y = to_screen(x);
+            renderer.line(x - 1, last, x, y, color.clone());
+            last = y;
+        }
+    }
+}
+
+impl Scene for PlotScene {
+    fn attach(&mut self, _app_info: &term_gfx::app::AppInfo) -> Result<(), SceneError> {
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), SceneError> {
+        Ok(())
+    }
+
+    fn update(&mut self, renderer: &mut Renderer, time: &Time) -> Result<SceneCommand, SceneError> {
+        let (w, h) = renderer.screen_size();
+
+        renderer.line(0, h / 2, w - 1, h / 2, Color::grey(80));
+
+        self.plot(renderer, Color::cyan(), |x| x.sin());
+        self.plot(renderer, Color::yellow(), |x| 0.5 * (2.0 * x).cos());
+
+        self.phase += 1.5 * time.delta.as_secs_f64();
+        Ok(SceneCommand::None)
+    }
+
+    fn event(&mut self, _event: &Event) -> Result<SceneCommand, SceneError> {
+        Ok(SceneCommand::None)
+    }
+}
+
+fn main() {
+    let cfg = AppStartupConfig {
+        fps: 30,
+        render_mode: RenderMode::Braille,
+        ..Default::default()
+    };
+
+    let scene = Box::new(PlotScene { phase: 0.0 });
+
+    match term_gfx::run(scene, cfg) {
+        Ok(status) => exit(status.code()),
+        Err(err) => {
+            eprintln!("Got an error: {:?}", err);
+            exit(-1);
+        }
+    }
+}
@@ -1,20 +1,19 @@
 use std::process::exit;
 
 use term_gfx::{
-    app::{AppError, AppStartupConfig, Scene},
-    event::Event,
+    app::{AppStartupConfig, Scene, SceneCommand, SceneError},
+    bindings::Bindings,
+    event::{Action, Event},
+    renderer::MouseMode,
+    time::Time,
     Color, Framebuffer, Renderer,
 };
 
-fn error_handler(err: AppError) {
-    eprintln!("Got an error: {:?}", err);
-    exit(-1);
-}
-
 struct ExampleScene {
     img: Framebuffer,
     img_sml: Framebuffer,
     img_small_size: (usize, usize),
+    mouse: Option<(i64, i64)>,
 }
 
 impl ExampleScene {
@@ -28,6 +27,7 @@ impl ExampleScene {
             img,
             img_sml,
             img_small_size,
+            mouse: None,
         }
     }
 
@@ -65,46 +65,117 @@ impl ExampleScene {
 }
 
 impl Scene for ExampleScene {
-    fn attach(&mut self, _app_info: &term_gfx::app::AppInfo) {}
+    fn attach(&mut self, app_info: &term_gfx::app::AppInfo) -> Result<(), SceneError> {
+        // The cursor is drawn once and only its layer is moved around.
+        let mut renderer = app_info.renderer.borrow_mut();
+        let cursor = renderer.layer("cursor");
+        cursor.set_z(1).set_visible(false);
+        cursor.line(0, 2, 4, 2, Color::grey(255));
+        cursor.line(2, 0, 2, 4, Color::grey(255));
+        Ok(())
+    }
 
-    fn detach(&mut self) {}
+    fn detach(&mut self) -> Result<(), SceneError> {
+        Ok(())
+    }
 
-    fn update(&mut self, renderer: &mut Renderer) {
+    fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
         self.draw_border(renderer);
         self.draw_triangle(renderer);
 
         renderer.line(10, 10, 30, 10, Color::rgb(255, 0, 255));
         renderer.draw_framebuffer(5, 5, &self.img);
         renderer.draw_framebuffer(5 + self.img.width() as i64 + 1, 5, &self.img_sml);
-    }
 
-    fn event(&mut self, event: &Event) {
-        if let Event::CharEvent(c) = event {
-            match c {
-                'w' => self.img_small_size.1 -= 1,
-                'a' => self.img_small_size.0 -= 1,
-                's' => self.img_small_size.1 += 1,
-                'd' => self.img_small_size.0 += 1,
-                _ => return,
-            }
+        if let Some((x, y)) = self.mouse {
+            renderer.layer("cursor").set_visible(true).set_offset(x - 2, y - 2);
+        }
 
-            if self.img_small_size.1 == 0 {
-                self.img_small_size.1 = 1;
+        Ok(SceneCommand::None)
+    }
+
+    fn event(&mut self, event: &Event) -> Result<SceneCommand, SceneError> {
+        match event {
+            Event::MouseMove(x, y) | Event::MouseButton(_, _, x, y) => {
+                self.mouse = Some((*x, *y));
+                return Ok(SceneCommand::None);
             }
-            if self.img_small_size.0 == 0 {
-                self.img_small_size.0 = 1;
+            Event::Scroll(_, dy) => {
+                self.img_small_size.0 = self.img_small_size.0.saturating_add_signed(*dy as isize);
+                self.img_small_size.1 = self.img_small_size.1.saturating_add_signed(*dy as isize);
             }
+            Event::Action(action, Action::Press) => match action.as_str() {
+                "shrink_y" => self.img_small_size.1 -= 1,
+                "shrink_x" => self.img_small_size.0 -= 1,
+                "grow_y" => self.img_small_size.1 += 1,
+                "grow_x" => self.img_small_size.0 += 1,
+                "pause" => return Ok(SceneCommand::Push(Box::new(PauseOverlay))),
+                "quit" => return Ok(SceneCommand::Quit(0)),
+                _ => return Ok(SceneCommand::None),
+            },
+            _ => return Ok(SceneCommand::None),
+        }
 
-            self.img_sml =
-                Framebuffer::new_resized(&self.img, self.img_small_size.0, self.img_small_size.1);
+        if self.img_small_size.1 == 0 {
+            self.img_small_size.1 = 1;
         }
+        if self.img_small_size.0 == 0 {
+            self.img_small_size.0 = 1;
+        }
+
+        self.img_sml =
+            Framebuffer::new_resized(&self.img, self.img_small_size.0, self.img_small_size.1);
+        Ok(SceneCommand::None)
+    }
+}
+
+/// Dims the screen until the next key press.
+struct PauseOverlay;
+
+impl Scene for PauseOverlay {
+    fn attach(&mut self, _app_info: &term_gfx::app::AppInfo) -> Result<(), SceneError> {
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), SceneError> {
+        Ok(())
+    }
+
+    fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
+        let (w, h) = renderer.screen_size();
+        for y in (0..h).step_by(2) {
+            renderer.line(0, y, w - 1, y, Color::grey(40));
+        }
+        Ok(SceneCommand::None)
+    }
+
+    fn event(&mut self, event: &Event) -> Result<SceneCommand, SceneError> {
+        match event {
+            Event::Key(_) => Ok(SceneCommand::Pop),
+            _ => Ok(SceneCommand::None),
+        }
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
     }
 }
 
 fn main() {
-    let cfg = AppStartupConfig { fps: 60 };
+    let cfg = AppStartupConfig {
+        fps: 60,
+        mouse_mode: MouseMode::AnyMotion,
+        bindings: Bindings::load("res/sandbox.bindings").unwrap(),
+        ..Default::default()
+    };
 
     let scene = Box::new(ExampleScene::new());
 
-    term_gfx::run(scene, cfg, error_handler);
+    match term_gfx::run(scene, cfg) {
+        Ok(status) => exit(status.code()),
+        Err(err) => {
+            eprintln!("Got an error: {:?}", err);
+            exit(-1);
+        }
+    }
 }
@@ -1,19 +1,60 @@
 use std::{
     borrow::BorrowMut,
     cell::RefCell,
+    error::Error,
     io,
+    panic,
+    path::PathBuf,
     rc::Rc,
     sync::{Arc, Mutex},
     thread::sleep,
     time::{Duration, Instant},
+    vec,
 };
 
 use crate::{
-    event::{Event, EventHandler}, framebuffer::{self, FramebufferError}, profile, profiler::Profiler, renderer, Framebuffer, Renderer
+    backend::restore_terminal, bindings::Bindings, color::ColorDepth, event::{Event, EventHandler}, framebuffer::{FramebufferError, RenderMode}, input::InputState, key::{KeyCode, KeyEventKind, Modifiers}, profile, profiler::Profiler, scene::SceneStack, recording::{Recorder, Recording, RecordingHeader}, renderer::{self, MouseMode, RendererOptions}, term, time::{Clock, Time, Timestep}, Renderer
 };
 
 pub struct AppStartupConfig {
+    /// Frames per second, with 0 a frame is only drawn when an event arrives (a replay then runs
+    /// as fast as possible).
     pub fps: u64,
+    pub render_mode: RenderMode,
+    pub color_depth: ColorDepth,
+    /// Clear color, retained frames and what happens on resize.
+    pub renderer_options: RendererOptions,
+    pub mouse_mode: MouseMode,
+    /// Asks the terminal to report key releases and repeats with the kitty keyboard protocol,
+    /// terminals that don't support it only report presses.
+    pub keyboard_enhancement: bool,
+    /// Key bindings for [`Event::Action`] events, can be changed later through
+    /// [`AppInfo::bindings`].
+    pub bindings: Bindings,
+    /// Records all events to this file, it can be replayed with [`AppStartupConfig::replay`].
+    pub record: Option<PathBuf>,
+    /// Hands these events to the scene instead of reading the terminal input, the app quits after
+    /// the last recorded frame.
+    pub replay: Option<Recording>,
+    /// Whether and how often [`Scene::fixed_update`] is called, independent of `fps`.
+    pub timestep: Timestep,
+}
+
+impl Default for AppStartupConfig {
+    fn default() -> Self {
+        AppStartupConfig {
+            fps: 60,
+            render_mode: RenderMode::Full,
+            color_depth: ColorDepth::detect(),
+            renderer_options: RendererOptions::default(),
+            mouse_mode: MouseMode::Disabled,
+            keyboard_enhancement: false,
+            bindings: Bindings::new(),
+            record: None,
+            replay: None,
+            timestep: Timestep::Variable,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -21,46 +62,162 @@ pub enum AppError {
     FBError(FramebufferError),
     RendererError(renderer::RendererError),
     IOError(io::Error),
+    /// A scene method returned an error.
+    SceneError(SceneError),
+    /// The Ctrl-C handler couldn't be installed, e.g. because another one already is.
+    SignalError(ctrlc::Error),
+}
+
+/// Set to false by the Ctrl-C handler. The handler can only be installed once per process, so it
+/// is shared by all runs.
+static RUNNING: Mutex<Option<Arc<Mutex<bool>>>> = Mutex::new(None);
+
+/// The flag the Ctrl-C handler clears, installs the handler on the first call.
+fn running_flag() -> Result<Arc<Mutex<bool>>, AppError> {
+    let mut running = RUNNING.lock().unwrap();
+    if let Some(running) = &*running {
+        return Ok(running.clone());
+    }
+
+    let flag = Arc::new(Mutex::new(false));
+    let handler_flag = flag.clone();
+
+    // Also handles SIGTERM and SIGHUP, so the app always shuts down cleanly. The loop might be
+    // waiting for input, so it is woken up.
+    match ctrlc::set_handler(move || {
+        *handler_flag.lock().unwrap() = false;
+        term::wake();
+    }) {
+        Ok(_) => (),
+        Err(e) => return Err(AppError::SignalError(e)),
+    }
+
+    *running = Some(flag.clone());
+    Ok(flag)
 }
 
-pub fn run<F>(scene: Box<dyn Scene>, startup_config: AppStartupConfig, error_handler: F)
-where
-    F: FnOnce(AppError) -> (),
-{
+/// Errors of scenes, they end the app and are returned by [`run`].
+pub type SceneError = Box<dyn Error>;
+
+/// Why an app stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// A scene returned [`SceneCommand::Quit`] with this code.
+    Quit(i32),
+    /// The last scene was popped or the replay ended.
+    Finished,
+    /// Stopped by Ctrl-C, SIGTERM or SIGHUP.
+    Interrupted,
+}
+
+impl ExitStatus {
+    /// The code the process should exit with, 130 after an interrupt like a shell would report it.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Quit(code) => *code,
+            ExitStatus::Finished => 0,
+            ExitStatus::Interrupted => 130,
+        }
+    }
+}
+
+/// Runs `scene` in the terminal until it quits, the terminal is restored when this returns.
+pub fn run(scene: Box<dyn Scene>, startup_config: AppStartupConfig) -> Result<ExitStatus, AppError> {
     let _p = match Profiler::new() {
         Ok(p) => p,
-        Err(e) => {
-            error_handler(AppError::IOError(e));
-            panic!("Error handler returned");
-        }
+        Err(e) => return Err(AppError::IOError(e)),
     };
 
-    let mut app = match App::new(scene, startup_config) {
-        Ok(app) => app,
-        Err(e) => {
-            error_handler(e);
-            panic!("Error handler returned")
+    let _hook = PanicHookGuard::install();
+
+    // The app is dropped before returning, so the terminal is already restored when the caller
+    // prints an error.
+    match App::new(scene, startup_config) {
+        Ok(mut app) => app.run(),
+        Err(e) => Err(e),
+    }
+}
+
+type PanicHook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Restores the terminal on a panic before the message is printed, otherwise it would be printed
+/// on the alternate screen and lost. Dropping it puts the previous hook back.
+struct PanicHookGuard {
+    previous: Option<Arc<PanicHook>>,
+}
+
+impl PanicHookGuard {
+    fn install() -> PanicHookGuard {
+        let previous = Arc::new(panic::take_hook());
+        let hook = previous.clone();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+        PanicHookGuard { previous: Some(previous) }
+    }
+}
+
+impl Drop for PanicHookGuard {
+    fn drop(&mut self) {
+        // The hook can't be changed while unwinding, the terminal is restored at most once anyway.
+        if std::thread::panicking() {
+            return;
         }
-    };
 
-    match app.run() {
-        Ok(_) => (),
-        Err(e) => {
-            error_handler(e);
-            panic!("Error handler returned")
+        // Dropping our hook drops its reference to the previous one, so it can be put back as is
+        // instead of wrapping it again on every run.
+        drop(panic::take_hook());
+        match self.previous.take().map(Arc::try_unwrap) {
+            Some(Ok(hook)) => panic::set_hook(hook),
+            Some(Err(hook)) => panic::set_hook(Box::new(move |info| hook(info))),
+            None => (),
         }
-    };
+    }
+}
+
+/// Changes the scene stack, returned by [`Scene::update`] and [`Scene::event`] of the top scene.
+pub enum SceneCommand {
+    None,
+    /// Puts a scene on top, the current one stays attached but gets no more events until the new
+    /// one is popped.
+    Push(Box<dyn Scene>),
+    /// Detaches and removes the top scene, the app quits when the last one is popped.
+    Pop,
+    /// Detaches the top scene and puts this one in its place.
+    Replace(Box<dyn Scene>),
+    /// Detaches all scenes and ends the app, [`run`] returns [`ExitStatus::Quit`] with the code.
+    Quit(i32),
 }
 
 pub trait Scene {
-    fn update(&mut self, renderer: &mut Renderer);
-    fn event(&mut self, event: &Event);
-    fn attach(&mut self, app_info: &AppInfo);
-    fn detach(&mut self);
+    /// Draws the next frame, `time` holds the time since the last frame.
+    ///
+    /// Scenes below an overlay are drawn first, but only the command of the top scene is used.
+    fn update(&mut self, renderer: &mut Renderer, time: &Time) -> Result<SceneCommand, SceneError>;
+    /// Advances the simulation by `time.delta`, only called for the top scene with a fixed
+    /// [`AppStartupConfig::timestep`], before `update`.
+    fn fixed_update(&mut self, _time: &Time) -> Result<(), SceneError> {
+        Ok(())
+    }
+    /// Only the top scene gets events.
+    fn event(&mut self, event: &Event) -> Result<SceneCommand, SceneError>;
+    /// Called when the scene is put on the stack.
+    fn attach(&mut self, app_info: &AppInfo) -> Result<(), SceneError>;
+    /// Called when the scene is removed from the stack or the app ends.
+    fn detach(&mut self) -> Result<(), SceneError>;
+    /// Overlays are drawn on top of the scene below them instead of replacing it, e.g. a pause
+    /// menu over the game.
+    fn is_overlay(&self) -> bool {
+        false
+    }
 }
 
 pub struct AppInfo {
     pub renderer: Rc<RefCell<Renderer>>,
+    /// Keyboard and mouse state, updated before the events of a frame are handed to the scene.
+    pub input: Rc<RefCell<InputState>>,
+    pub bindings: Rc<RefCell<Bindings>>,
 }
 
 impl AppInfo {}
@@ -69,104 +226,407 @@ impl Clone for AppInfo {
     fn clone(&self) -> Self {
         AppInfo {
             renderer: self.renderer.clone(),
+            input: self.input.clone(),
+            bindings: self.bindings.clone(),
         }
     }
 }
 
-struct App {
-    sleep_time: Duration,
+/// The screen `renderer` starts with, written at the top of a recording.
+fn recording_header(renderer: &Renderer) -> RecordingHeader {
+    let (width, height) = renderer.screen_size();
+    let (cell_width, cell_height) = renderer.render_mode().cell_size();
+    RecordingHeader {
+        width: width as usize / cell_width,
+        height: height as usize / cell_height,
+        render_mode: renderer.render_mode(),
+    }
+}
+
+/// Whether `event` is a Ctrl-C press, which the terminal sends as a key while the kitty keyboard
+/// protocol is active.
+fn is_ctrl_c(event: &Event) -> bool {
+    match event {
+        Event::Key(k) => {
+            matches!(k.code, KeyCode::Char('c' | 'C'))
+                && k.modifiers.contains(Modifiers::CTRL)
+                && k.kind == KeyEventKind::Press
+        }
+        _ => false,
+    }
+}
+
+pub(crate) struct App {
+    /// Reads the terminal input, `None` for apps that get their events from elsewhere. Declared
+    /// first so it is dropped, and resets the keyboard protocol, before the renderer leaves the
+    /// alternate screen.
+    event_handler: Option<EventHandler>,
+    /// The kitty keyboard protocol is active, Ctrl-C then arrives as a key event instead of
+    /// raising SIGINT.
+    keyboard_enhanced: bool,
+    /// Time per frame, `None` to wait for the next event instead.
+    sleep_time: Option<Duration>,
+    clock: Clock,
+    recorder: Option<Recorder>,
+    /// The remaining frames of a replay.
+    replay: Option<vec::IntoIter<Vec<Event>>>,
     app_info: AppInfo,
-    scene: Box<dyn Scene>,
-    event_handler: EventHandler,
+    scenes: SceneStack,
+    /// Set once the app should stop.
+    exit: Option<ExitStatus>,
     pub running: Arc<Mutex<bool>>,
 }
 
 impl App {
     fn new(scene: Box<dyn Scene>, startup_config: AppStartupConfig) -> Result<App, AppError> {
         profile!();
-        let renderer = match Renderer::new() {
+        // Before the terminal is touched, so a failure leaves it alone.
+        let running = running_flag()?;
+
+        let mut renderer = match Renderer::new(startup_config.render_mode, startup_config.color_depth) {
             Ok(renderer) => renderer,
             Err(e) => return Err(AppError::RendererError(e)),
         };
+        renderer.set_options(startup_config.renderer_options);
+        match renderer.set_mouse_mode(startup_config.mouse_mode) {
+            Ok(_) => (),
+            Err(e) => return Err(AppError::RendererError(e)),
+        }
+
+        let mut event_handler = match EventHandler::new(startup_config.render_mode) {
+            Ok(event_handler) => event_handler,
+            Err(e) => return Err(AppError::IOError(e)),
+        };
+        let key_releases = if startup_config.keyboard_enhancement {
+            match event_handler.enable_keyboard_enhancement() {
+                Ok(supported) => supported,
+                Err(e) => return Err(AppError::IOError(e)),
+            }
+        } else {
+            false
+        };
+        match event_handler.supports_synchronized_output() {
+            Ok(supported) => renderer.set_synchronized_output(supported),
+            Err(e) => return Err(AppError::IOError(e)),
+        }
 
-        let running = Arc::new(Mutex::new(false));
-        let mut running_closure = running.clone();
+        let recorder = match startup_config.record {
+            Some(path) => match Recorder::create(path, recording_header(&renderer)) {
+                Ok(recorder) => Some(recorder),
+                Err(e) => return Err(AppError::IOError(e)),
+            },
+            None => None,
+        };
 
-        ctrlc::set_handler(move || {
-            *running_closure.borrow_mut().lock().unwrap() = false;
-        })
-        .unwrap();
+        let frame_time = match startup_config.fps {
+            0 => None,
+            fps => Some(Duration::from_millis(1000 / fps)),
+        };
+        // A replay should behave the same no matter how fast the frames are drawn, frames without
+        // a recorded frame time take 1/fps.
+        let simulated = match startup_config.replay {
+            Some(_) => frame_time,
+            None => None,
+        };
 
-        let event_handler = EventHandler::new();
+        let mut clock = Clock::new(startup_config.timestep, simulated);
+        if let Some(replay) = &startup_config.replay {
+            clock.script(&replay.frame_times);
+        }
 
         Ok(App {
-            sleep_time: Duration::from_millis(1000 / startup_config.fps),
-            scene,
+            clock,
+            recorder,
+            replay: startup_config.replay.map(|r| r.frame_events().into_iter()),
+            sleep_time: frame_time,
+            scenes: SceneStack::new(scene),
+            exit: None,
             running,
-            event_handler,
+            event_handler: Some(event_handler),
+            keyboard_enhanced: key_releases,
             app_info: AppInfo {
                 renderer: Rc::new(RefCell::new(renderer)),
+                input: Rc::new(RefCell::new(InputState::new(key_releases))),
+                bindings: Rc::new(RefCell::new(startup_config.bindings)),
             },
         })
     }
 
-    fn run_iteration(&mut self, events: &Vec<Event>) -> Result<(), AppError> {
-        profile!();
-        let start = Instant::now();
+    /// An app that doesn't touch the terminal, it neither reads input nor sleeps between frames.
+    /// Every frame pretends to take its entry in `frame_times`, or `frame_time` after those ran out.
+    /// `keyboard_enhanced` pretends the kitty keyboard protocol is active.
+    pub(crate) fn headless(
+        scene: Box<dyn Scene>,
+        renderer: Renderer,
+        bindings: Bindings,
+        timestep: Timestep,
+        frame_time: Duration,
+        frame_times: &[Duration],
+        keyboard_enhanced: bool,
+    ) -> App {
+        let mut clock = Clock::new(timestep, Some(frame_time));
+        clock.script(frame_times);
+
+        App {
+            clock,
+            sleep_time: Some(Duration::ZERO),
+            recorder: None,
+            replay: None,
+            scenes: SceneStack::new(scene),
+            exit: None,
+            running: Arc::new(Mutex::new(false)),
+            event_handler: None,
+            keyboard_enhanced,
+            app_info: AppInfo {
+                renderer: Rc::new(RefCell::new(renderer)),
+                input: Rc::new(RefCell::new(InputState::new(keyboard_enhanced))),
+                bindings: Rc::new(RefCell::new(bindings)),
+            },
+        }
+    }
+
+    pub(crate) fn app_info(&self) -> &AppInfo {
+        &self.app_info
+    }
+
+    pub(crate) fn attach(&mut self) -> Result<(), AppError> {
+        match self.scenes.attach(&self.app_info) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(AppError::SceneError(e)),
+        }
+    }
+
+    pub(crate) fn detach(&mut self) -> Result<(), AppError> {
+        match self.scenes.detach() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(AppError::SceneError(e)),
+        }
+    }
+
+    /// Why the app should stop, `None` while it keeps running.
+    pub(crate) fn exit_status(&self) -> Option<ExitStatus> {
+        match self.exit {
+            Some(status) => Some(status),
+            None if self.scenes.is_empty() => Some(ExitStatus::Finished),
+            None => None,
+        }
+    }
+
+    fn apply(&mut self, command: Result<SceneCommand, SceneError>) -> Result<(), AppError> {
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => return Err(AppError::SceneError(e)),
+        };
+
+        match self.scenes.apply(command, &self.app_info) {
+            Ok(Some(code)) => self.exit = Some(ExitStatus::Quit(code)),
+            Ok(None) => (),
+            Err(e) => return Err(AppError::SceneError(e)),
+        }
+        Ok(())
+    }
+
+    fn scene_event(&mut self, event: &Event) -> Result<(), AppError> {
+        match self.scenes.top() {
+            Some(scene) => {
+                let command = scene.event(event);
+                self.apply(command)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Hands the events to the top scene and lets the visible scenes draw the next frame. Stops
+    /// early once a scene asks to quit.
+    pub(crate) fn update(&mut self, events: &[Event]) -> Result<(), AppError> {
+        let (steps, time) = self.clock.tick();
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(time.delta, events) {
+                return Err(AppError::IOError(e));
+            }
+        }
+
+        {
+            let mut input = self.app_info.input.as_ref().borrow_mut();
+            input.begin_frame();
+            for e in events {
+                input.handle(e);
+            }
+        }
 
         for e in events {
             profile!("User Scene Event");
 
+            if self.keyboard_enhanced && is_ctrl_c(e) {
+                *self.running.borrow_mut().lock().unwrap() = false;
+                self.exit = Some(ExitStatus::Interrupted);
+                return Ok(());
+            }
+
             if let Event::Resize(w, h) = e {
                 self.app_info.renderer.as_ref().borrow_mut().resize(*w, *h);
             }
 
-            self.scene.event(e);
+            let changes = self.scenes.changes();
+            self.scene_event(e)?;
+
+            // The scene may rebind while handling the event, so the borrow ends before it runs.
+            // The action belongs to the scene that got the event, if that scene pushed or popped
+            // one the action is dropped.
+            let action = self.app_info.bindings.as_ref().borrow().translate(e);
+            if let Some(action) = action {
+                if self.scenes.changes() == changes {
+                    self.scene_event(&action)?;
+                }
+            }
+
+            if self.exit_status().is_some() {
+                return Ok(());
+            }
         }
 
+        for step in &steps {
+            profile!("User Scene Fixed Update");
+            if let Some(scene) = self.scenes.top() {
+                if let Err(e) = scene.fixed_update(step) {
+                    return Err(AppError::SceneError(e));
+                }
+            }
+        }
+
+        let mut command = Ok(SceneCommand::None);
         {
             profile!("User Scene Update");
-            self.scene
-                .update(&mut self.app_info.renderer.as_ref().borrow_mut());
-        }
+            let mut renderer = self.app_info.renderer.as_ref().borrow_mut();
+            let visible = self.scenes.visible();
+            let top = visible.len().saturating_sub(1);
 
+            for (i, scene) in visible.iter_mut().enumerate() {
+                let c = scene.update(&mut renderer, &time);
+                if i == top {
+                    command = c;
+                } else if let Err(e) = c {
+                    return Err(AppError::SceneError(e));
+                }
+            }
+        }
+        self.apply(command)
+    }
 
+    pub(crate) fn present(&mut self) -> Result<(), AppError> {
         match self.app_info.renderer.as_ref().borrow_mut().render() {
-            Ok(_) => (),
-            Err(e) => return Err(AppError::RendererError(e)),
+            Ok(_) => Ok(()),
+            Err(e) => Err(AppError::RendererError(e)),
+        }
+    }
+
+    fn run_iteration(&mut self, events: &[Event]) -> Result<(), AppError> {
+        profile!();
+        let start = Instant::now();
+
+        self.update(events)?;
+        if self.exit_status().is_some() {
+            return Ok(());
         }
+        self.present()?;
 
         let loop_time = Instant::now() - start;
-        if loop_time < self.sleep_time {
-            profile!("Sleep");
-            sleep(self.sleep_time - loop_time);
+        match (self.sleep_time, &self.event_handler) {
+            // A replay doesn't read the terminal, so it must not wait for input either.
+            (None, _) if self.replay.is_some() => (),
+            (Some(sleep_time), _) => {
+                if loop_time < sleep_time {
+                    profile!("Sleep");
+                    sleep(sleep_time - loop_time);
+                }
+            }
+            (None, Some(event_handler)) => {
+                profile!("Wait");
+                if let Err(e) = event_handler.wait(None) {
+                    return Err(AppError::IOError(e));
+                }
+            }
+            (None, None) => (),
         }
         Ok(())
     }
 
-    fn run(&mut self) -> Result<(), AppError> {
+    fn run(&mut self) -> Result<ExitStatus, AppError> {
         profile!();
-        // Setup
         *self.running.borrow_mut().lock().unwrap() = true;
-        self.scene.attach(&self.app_info);
+        let result = self.run_loop();
 
-        // Loop
-        while *self.running.borrow_mut().lock().unwrap() {
-            let events = self.event_handler.get_events();
+        // Cleanup, scenes are detached and the recording is finished even after an error.
+        let detached = self.detach();
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.finish() {
+                return Err(AppError::IOError(e));
+            }
+        }
+
+        let status = result?;
+        detached?;
+        Ok(status)
+    }
+
+    fn run_loop(&mut self) -> Result<ExitStatus, AppError> {
+        self.attach()?;
+
+        loop {
+            if !*self.running.borrow_mut().lock().unwrap() {
+                return Ok(ExitStatus::Interrupted);
+            }
+            if let Some(status) = self.exit_status() {
+                return Ok(status);
+            }
 
-            // for e in events {
-            //     println!("{:?}", e);
-            // }
+            let events = match (&mut self.replay, &mut self.event_handler) {
+                (Some(replay), _) => match replay.next() {
+                    Some(events) => events,
+                    None => return Ok(ExitStatus::Finished),
+                },
+                (None, Some(event_handler)) => match event_handler.get_events() {
+                    Ok(events) => events,
+                    Err(e) => return Err(AppError::IOError(e)),
+                },
+                (None, None) => vec![],
+            };
 
             self.run_iteration(&events)?;
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic, sync::Arc};
+
+    use crate::app::{running_flag, PanicHookGuard};
 
-        // Cleanup
-        let (_, h) = self.app_info.renderer.as_ref().borrow_mut().screen_size();
-        for _ in 0..h {
-            println!();
+    #[test]
+    fn test_running_flag_is_shared_between_runs() {
+        // A second run must not try to install the Ctrl-C handler again.
+        let first = running_flag().unwrap();
+        let second = running_flag().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_panic_hook_is_restored() {
+        let marker = Box::new(1);
+        let hook: Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send> = Box::new(move |_| {
+            let _ = &marker;
+        });
+        let original = &*hook as *const _ as *const ();
+        panic::set_hook(hook);
+
+        // Runs one after the other get the very same hook back, not a wrapper around it.
+        for _ in 0..2 {
+            drop(PanicHookGuard::install());
         }
 
-        Ok(())
+        let hook = panic::take_hook();
+        assert_eq!(&*hook as *const _ as *const (), original);
     }
 }
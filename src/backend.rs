@@ -0,0 +1,236 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, stdout, BufWriter, Stdout, Write},
+    path::Path,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::term;
+
+/// Set once [`restore_terminal`] ran, all later terminal output is dropped so nothing is drawn
+/// over what is printed after the restoration (e.g. a panic message).
+static TERMINAL_RESTORED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the kitty keyboard protocol flags are pushed, they have to be popped before leaving the
+/// alternate screen because the terminal keeps a separate stack for each screen.
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+
+/// Escape sequences that undo everything the renderer changed: synchronized output, colors, mouse,
+/// paste and focus reporting, cursor and alternate screen.
+const RESTORE_SEQUENCE: &[u8] = b"\x1b[?2026l\x1b[0m\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l\
+    \x1b[?2004l\x1b[?1004l\x1b[?25h\x1b[?1049l";
+
+/// Puts the terminal back into the state it was in before the app started, safe to call from a
+/// panic hook or more than once.
+pub(crate) fn restore_terminal() {
+    restore_terminal_to(&mut stdout(), &TERMINAL_RESTORED);
+}
+
+/// Writes the restore sequences to `out` unless `restored` is already set.
+fn restore_terminal_to<W: Write>(out: &mut W, restored: &AtomicBool) {
+    if restored.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    pop_keyboard_flags_to(out);
+    let _ = out.write_all(RESTORE_SEQUENCE);
+    let _ = out.flush();
+
+    term::restore_saved();
+}
+
+/// Enables the kitty keyboard protocol with `flags` until [`pop_keyboard_flags`] is called.
+pub(crate) fn push_keyboard_flags(flags: u32) -> io::Result<()> {
+    let mut out = stdout();
+    write!(out, "\x1b[>{}u", flags)?;
+    out.flush()?;
+    KEYBOARD_ENHANCED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Undoes [`push_keyboard_flags`], does nothing if the flags are not pushed.
+pub(crate) fn pop_keyboard_flags() {
+    pop_keyboard_flags_to(&mut stdout());
+}
+
+fn pop_keyboard_flags_to<W: Write>(out: &mut W) {
+    if KEYBOARD_ENHANCED.swap(false, Ordering::SeqCst) {
+        let _ = out.write_all(b"\x1b[<u");
+        let _ = out.flush();
+    }
+}
+
+/// Output a [`Renderer`](crate::Renderer) presents its frames through.
+pub trait Backend: Write {
+    /// Size of the output in terminal cells (columns, rows), `None` if it can't be determined.
+    fn size(&self) -> Option<(usize, usize)>;
+}
+
+/// The real terminal, frames are written to stdout.
+pub struct TerminalBackend {
+    out: Stdout,
+}
+
+impl TerminalBackend {
+    pub fn new() -> TerminalBackend {
+        TERMINAL_RESTORED.store(false, Ordering::SeqCst);
+        TerminalBackend { out: stdout() }
+    }
+}
+
+impl Default for TerminalBackend {
+    fn default() -> Self {
+        TerminalBackend::new()
+    }
+}
+
+impl Write for TerminalBackend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if TERMINAL_RESTORED.load(Ordering::SeqCst) {
+            return Ok(buf.len());
+        }
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Backend for TerminalBackend {
+    fn size(&self) -> Option<(usize, usize)> {
+        term_size::dimensions()
+    }
+}
+
+/// Collects all output in memory, clones share the same buffer so the output can be inspected
+/// after the backend was handed to a renderer.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    buffer: Rc<RefCell<Vec<u8>>>,
+    width: usize,
+    height: usize,
+}
+
+impl MemoryBackend {
+    pub fn new(width: usize, height: usize) -> MemoryBackend {
+        MemoryBackend {
+            buffer: Rc::new(RefCell::new(Vec::new())),
+            width,
+            height,
+        }
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+
+    /// Removes and returns everything written so far.
+    pub fn take(&self) -> Vec<u8> {
+        self.buffer.borrow_mut().split_off(0)
+    }
+}
+
+impl Write for MemoryBackend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn size(&self) -> Option<(usize, usize)> {
+        Some((self.width, self.height))
+    }
+}
+
+/// Writes to any [`Write`], e.g. a pipe or a file, pretending to be a terminal of a fixed size.
+pub struct WriterBackend<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> WriterBackend<W> {
+    pub fn new(out: W, width: usize, height: usize) -> WriterBackend<W> {
+        WriterBackend { out, width, height }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl WriterBackend<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path` and writes all frames into it, `cat`ing the file
+    /// in a terminal of the same size replays them.
+    pub fn create<P: AsRef<Path>>(path: P, width: usize, height: usize) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(WriterBackend::new(BufWriter::new(file), width, height))
+    }
+}
+
+impl<W: Write> Write for WriterBackend<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Backend for WriterBackend<W> {
+    fn size(&self) -> Option<(usize, usize)> {
+        Some((self.width, self.height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use crate::{
+        backend::{restore_terminal_to, MemoryBackend, RESTORE_SEQUENCE},
+        color::ColorDepth,
+        framebuffer::RenderMode,
+        Renderer,
+    };
+
+    #[test]
+    fn test_restore_terminal_only_once() {
+        let restored = AtomicBool::new(false);
+        let mut out = MemoryBackend::new(3, 2);
+
+        // E.g. from the panic hook and again while unwinding.
+        restore_terminal_to(&mut out, &restored);
+        restore_terminal_to(&mut out, &restored);
+
+        assert_eq!(out.take(), RESTORE_SEQUENCE);
+    }
+
+    #[test]
+    fn test_render_to_memory() {
+        let backend = MemoryBackend::new(3, 2);
+        let mut renderer =
+            Renderer::with_backend(Box::new(backend.clone()), RenderMode::Full, ColorDepth::TrueColor)
+                .unwrap();
+        assert_eq!(renderer.screen_size(), (3, 2));
+
+        renderer.render().unwrap();
+        assert!(!backend.take().is_empty());
+
+        renderer.render().unwrap();
+        assert!(backend.take().is_empty());
+
+        drop(renderer);
+        assert!(backend.take().ends_with(b"\x1b[?1049l"));
+    }
+}
@@ -0,0 +1,347 @@
+use std::{collections::BTreeMap, fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{
+    event::{Action, Event, Mouse},
+    key::{KeyCode, KeyEventKind, Modifiers},
+};
+
+/// A key with modifiers or a mouse button that can be bound to an action.
+///
+/// Characters are lowercase, an uppercase character is the lowercase one with shift. Other
+/// characters never have shift, it is already part of the character (`+` instead of shift+`=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(KeyCode, Modifiers),
+    Mouse(Mouse),
+}
+
+#[derive(Debug)]
+pub enum BindingError {
+    /// The input is already bound to the action.
+    Conflict(Input, String),
+    ParseError(String),
+    IoError(io::Error),
+}
+
+impl Input {
+    pub fn key(code: KeyCode, modifiers: Modifiers) -> Input {
+        match code {
+            KeyCode::Char(c) if c.is_uppercase() => {
+                Input::Key(KeyCode::Char(c.to_lowercase().next().unwrap_or(c)), modifiers | Modifiers::SHIFT)
+            }
+            KeyCode::Char(c) if !c.is_alphabetic() => Input::Key(code, modifiers - Modifiers::SHIFT),
+            code => Input::Key(code, modifiers),
+        }
+    }
+
+    /// The input that triggered `event` and whether it was pressed or released, `None` for other
+    /// events and key repeats.
+    pub fn from_event(event: &Event) -> Option<(Input, Action)> {
+        match event {
+            Event::Key(k) => match k.kind {
+                KeyEventKind::Press => Some((Input::key(k.code, k.modifiers), Action::Press)),
+                KeyEventKind::Release => Some((Input::key(k.code, k.modifiers), Action::Release)),
+                KeyEventKind::Repeat => None,
+            },
+            Event::MouseButton(button, action, _, _) => Some((Input::Mouse(*button), *action)),
+            _ => None,
+        }
+    }
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("space", KeyCode::Char(' ')),
+    // ',' separates the inputs in the config format.
+    ("comma", KeyCode::Char(',')),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("esc", KeyCode::Esc),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("insert", KeyCode::Insert),
+    ("delete", KeyCode::Delete),
+];
+
+const MODIFIER_NAMES: &[(&str, Modifiers)] = &[
+    ("ctrl", Modifiers::CTRL),
+    ("alt", Modifiers::ALT),
+    ("shift", Modifiers::SHIFT),
+    ("super", Modifiers::SUPER),
+];
+
+const MOUSE_NAMES: &[(&str, Mouse)] = &[
+    ("mouse:left", Mouse::Left),
+    ("mouse:middle", Mouse::Middle),
+    ("mouse:right", Mouse::Right),
+];
+
+/// Parses names like `w`, `ctrl+s`, `shift+up`, `f5` or `mouse:left`.
+impl FromStr for Input {
+    type Err = BindingError;
+
+    fn from_str(s: &str) -> Result<Input, BindingError> {
+        let err = || BindingError::ParseError(format!("unknown input \"{}\"", s));
+
+        if let Some((_, button)) = MOUSE_NAMES.iter().find(|(name, _)| *name == s) {
+            return Ok(Input::Mouse(*button));
+        }
+
+        // A lone "+" is the plus key, not a separator.
+        let (mods, key) = match s.rsplit_once('+') {
+            Some((mods, "")) => (mods.strip_suffix('+').unwrap_or(""), "+"),
+            Some((mods, key)) => (mods, key),
+            None => ("", s),
+        };
+
+        let mut modifiers = Modifiers::NONE;
+        for m in mods.split('+').filter(|m| !m.is_empty()) {
+            match MODIFIER_NAMES.iter().find(|(name, _)| *name == m) {
+                Some((_, modifier)) => modifiers |= *modifier,
+                None => return Err(err()),
+            }
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match KEY_NAMES.iter().find(|(name, _)| *name == key) {
+                Some((_, code)) => *code,
+                None => match key.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(err()),
+                },
+            },
+        };
+
+        Ok(Input::key(code, modifiers))
+    }
+}
+
+/// Writes the name [`Input::from_str`] parses.
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (code, modifiers) = match self {
+            Input::Mouse(button) => {
+                let (name, _) = MOUSE_NAMES.iter().find(|(_, b)| b == button).unwrap();
+                return write!(f, "{}", name);
+            }
+            Input::Key(code, modifiers) => (code, modifiers),
+        };
+
+        for (name, modifier) in MODIFIER_NAMES {
+            if modifiers.contains(*modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+
+        match KEY_NAMES.iter().find(|(_, c)| c == code) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => match code {
+                KeyCode::Char(c) => write!(f, "{}", c),
+                KeyCode::F(n) => write!(f, "f{}", n),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+/// Named actions bound to one or more inputs, each input belongs to at most one action.
+///
+/// The config file has one action per line, followed by its inputs:
+///
+/// ```text
+/// # Comments start with '#'
+/// up = w, up
+/// save = ctrl+s
+/// fire = space, mouse:left
+/// next = comma
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Bindings {
+    actions: BTreeMap<String, Vec<Input>>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        Bindings::default()
+    }
+
+    /// The action `input` is bound to.
+    pub fn action(&self, input: Input) -> Option<&str> {
+        self.actions
+            .iter()
+            .find(|(_, inputs)| inputs.contains(&input))
+            .map(|(action, _)| action.as_str())
+    }
+
+    /// The inputs bound to `action`.
+    pub fn inputs(&self, action: &str) -> &[Input] {
+        self.actions.get(action).map_or(&[], |inputs| inputs.as_slice())
+    }
+
+    /// All actions in alphabetical order.
+    pub fn actions(&self) -> impl Iterator<Item = &str> {
+        self.actions.keys().map(|a| a.as_str())
+    }
+
+    /// Binds `input` to `action`, fails if it is already bound to another action.
+    pub fn bind(&mut self, action: &str, input: Input) -> Result<(), BindingError> {
+        match self.action(input) {
+            Some(a) if a == action => Ok(()),
+            Some(a) => Err(BindingError::Conflict(input, a.to_string())),
+            None => {
+                self.actions.entry(action.to_string()).or_default().push(input);
+                Ok(())
+            }
+        }
+    }
+
+    /// Replaces the inputs of `action`, nothing changes if one of them is bound to another action.
+    pub fn rebind(&mut self, action: &str, inputs: &[Input]) -> Result<(), BindingError> {
+        for input in inputs {
+            match self.action(*input) {
+                Some(a) if a != action => return Err(BindingError::Conflict(*input, a.to_string())),
+                _ => (),
+            }
+        }
+
+        let bound = self.actions.entry(action.to_string()).or_default();
+        bound.clear();
+        for input in inputs {
+            if !bound.contains(input) {
+                bound.push(*input);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `input` from whatever action it is bound to.
+    pub fn unbind(&mut self, input: Input) {
+        for inputs in self.actions.values_mut() {
+            inputs.retain(|i| *i != input);
+        }
+    }
+
+    /// The action event for `event`, e.g. `Event::Action("up", Action::Press)` for a press of a
+    /// key bound to "up".
+    pub fn translate(&self, event: &Event) -> Option<Event> {
+        let (input, action) = Input::from_event(event)?;
+        self.action(input).map(|a| Event::Action(a.to_string(), action))
+    }
+
+    /// Parses the config format, conflicting bindings are an error.
+    pub fn from_config(text: &str) -> Result<Bindings, BindingError> {
+        let mut bindings = Bindings::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (action, inputs) = match line.split_once('=') {
+                Some((action, inputs)) if !action.trim().is_empty() => (action.trim(), inputs),
+                _ => return Err(BindingError::ParseError(format!("line {}: expected \"<action> = <inputs>\"", i + 1))),
+            };
+
+            for input in inputs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let input = match input.parse() {
+                    Ok(input) => input,
+                    Err(BindingError::ParseError(msg)) => {
+                        return Err(BindingError::ParseError(format!("line {}: {}", i + 1, msg)))
+                    }
+                    Err(e) => return Err(e),
+                };
+                bindings.bind(action, input)?;
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn to_config(&self) -> String {
+        let mut text = String::new();
+        for (action, inputs) in &self.actions {
+            let inputs: Vec<String> = inputs.iter().map(|i| i.to_string()).collect();
+            text.push_str(&format!("{} = {}\n", action, inputs.join(", ")));
+        }
+        text
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Bindings, BindingError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Bindings::from_config(&text),
+            Err(e) => Err(BindingError::IoError(e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), BindingError> {
+        match fs::write(path, self.to_config()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(BindingError::IoError(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bindings::{BindingError, Bindings, Input},
+        event::{Action, Event, Mouse},
+        key::{KeyCode, KeyEvent, Modifiers},
+    };
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = "# movement\nup = w, up\nsave = ctrl+s\nfire = space, mouse:left\nzoom = shift+f5, +\nnext = comma\n";
+        let bindings = Bindings::from_config(config).unwrap();
+
+        assert_eq!(bindings.action(Input::key(KeyCode::Up, Modifiers::NONE)), Some("up"));
+        assert_eq!(bindings.action(Input::key(KeyCode::Char('s'), Modifiers::CTRL)), Some("save"));
+        assert_eq!(bindings.action(Input::key(KeyCode::Char('s'), Modifiers::NONE)), None);
+        assert_eq!(bindings.action(Input::Mouse(Mouse::Left)), Some("fire"));
+        assert_eq!(bindings.action(Input::key(KeyCode::F(5), Modifiers::SHIFT)), Some("zoom"));
+        assert_eq!(bindings.action(Input::key(KeyCode::Char('+'), Modifiers::NONE)), Some("zoom"));
+        assert_eq!(bindings.action(Input::key(KeyCode::Char(','), Modifiers::NONE)), Some("next"));
+        // The kitty keyboard protocol reports the shift that was needed to type "+".
+        assert_eq!(bindings.action(Input::key(KeyCode::Char('+'), Modifiers::SHIFT)), Some("zoom"));
+        assert_eq!(
+            bindings.translate(&Event::Key(KeyEvent::new(KeyCode::Char('+'), Modifiers::SHIFT))),
+            Some(Event::Action("zoom".to_string(), Action::Press))
+        );
+
+        let text = bindings.to_config();
+        assert!(text.contains("next = comma\n"));
+        assert_eq!(Bindings::from_config(&text).unwrap().to_config(), text);
+
+        assert!(matches!(Bindings::from_config("up = w\ndown = w"), Err(BindingError::Conflict(_, a)) if a == "up"));
+        assert!(matches!(Bindings::from_config("up = foo"), Err(BindingError::ParseError(_))));
+    }
+
+    #[test]
+    fn test_rebind_and_translate() {
+        let mut bindings = Bindings::from_config("up = w\ndown = s").unwrap();
+        let w = Event::Key(KeyCode::Char('w').into());
+
+        assert_eq!(bindings.translate(&w), Some(Event::Action("up".to_string(), Action::Press)));
+        assert!(matches!(bindings.rebind("down", &["w".parse().unwrap()]), Err(BindingError::Conflict(_, _))));
+
+        bindings.rebind("up", &["i".parse().unwrap()]).unwrap();
+        assert_eq!(bindings.translate(&w), None);
+        assert_eq!(
+            bindings.translate(&Event::Key(KeyEvent::new(KeyCode::Char('I'), Modifiers::NONE))),
+            None
+        );
+        assert_eq!(
+            bindings.translate(&Event::Key(KeyCode::Char('i').into())),
+            Some(Event::Action("up".to_string(), Action::Press))
+        );
+    }
+}
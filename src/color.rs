@@ -1,4 +1,4 @@
-use std::io::Error;
+use std::{env, io::Error};
 
 #[derive(Debug, PartialEq)]
 pub struct Color {
@@ -78,27 +78,201 @@ impl Color {
         out.write(b"\x1b[0m")
     }
 
+    fn sgr(&self, layer: u8) -> [u8; 19] {
+        let mut setter: [u8; 19] = *b"\x1b[38;2;000;000;000m";
+        setter[2] = layer;
+
+        setter[7..10].copy_from_slice(&Color::color_to_c_str(self.red));
+        setter[11..14].copy_from_slice(&Color::color_to_c_str(self.green));
+        setter[15..18].copy_from_slice(&Color::color_to_c_str(self.blue));
+
+        setter
+    }
+
+    /// Sets both the foreground and the background color.
     pub fn apply<R>(&self, out: &mut R) -> Result<usize, Error>
     where
         R: std::io::Write,
     {
-        let mut setter: [u8; 38] = *b"\x1b[38;2;000;000;000m\x1b[48;2;000;000;000m";
-
-        let r = Color::color_to_c_str(self.red);
-        let g = Color::color_to_c_str(self.green);
-        let b = Color::color_to_c_str(self.blue);
-
-        for i in 0..=2 {
-            setter[i + 7] = r[i];
-            setter[i + 26] = r[i];
-            setter[i + 11] = g[i];
-            setter[i + 30] = g[i];
-            setter[i + 15] = b[i];
-            setter[i + 34] = b[i];
-        }
+        let mut setter: [u8; 38] = [0; 38];
+
+        setter[..19].copy_from_slice(&self.sgr(b'3'));
+        setter[19..].copy_from_slice(&self.sgr(b'4'));
 
         out.write(&setter)
     }
+
+    pub fn apply_fg<R>(&self, out: &mut R) -> Result<usize, Error>
+    where
+        R: std::io::Write,
+    {
+        out.write(&self.sgr(b'3'))
+    }
+
+    pub fn apply_bg<R>(&self, out: &mut R) -> Result<usize, Error>
+    where
+        R: std::io::Write,
+    {
+        out.write(&self.sgr(b'4'))
+    }
+
+    /// Sets the foreground to the color of `depth`'s palette that is nearest to this one.
+    pub fn apply_fg_in<R>(&self, out: &mut R, depth: ColorDepth) -> Result<usize, Error>
+    where
+        R: std::io::Write,
+    {
+        match depth {
+            ColorDepth::TrueColor => self.apply_fg(out),
+            ColorDepth::Ansi256 => out.write(format!("\x1b[38;5;{}m", depth.nearest(self).0).as_bytes()),
+            ColorDepth::Ansi16 => match depth.nearest(self).0 {
+                i @ 0..=7 => out.write(format!("\x1b[{}m", 30 + i).as_bytes()),
+                i => out.write(format!("\x1b[{}m", 90 + i - 8).as_bytes()),
+            },
+            // Monochrome cells are drawn in the default colors, white backgrounds use reverse video.
+            ColorDepth::Monochrome => Ok(0),
+        }
+    }
+
+    /// Sets the background to the color of `depth`'s palette that is nearest to this one.
+    pub fn apply_bg_in<R>(&self, out: &mut R, depth: ColorDepth) -> Result<usize, Error>
+    where
+        R: std::io::Write,
+    {
+        match depth {
+            ColorDepth::TrueColor => self.apply_bg(out),
+            ColorDepth::Ansi256 => out.write(format!("\x1b[48;5;{}m", depth.nearest(self).0).as_bytes()),
+            ColorDepth::Ansi16 => match depth.nearest(self).0 {
+                i @ 0..=7 => out.write(format!("\x1b[{}m", 40 + i).as_bytes()),
+                i => out.write(format!("\x1b[{}m", 100 + i - 8).as_bytes()),
+            },
+            ColorDepth::Monochrome => match depth.nearest(self).0 {
+                0 => out.write(b"\x1b[27m"),
+                _ => out.write(b"\x1b[7m"),
+            },
+        }
+    }
+
+    /// Squared euclidean distance between two colors.
+    pub(crate) fn distance(&self, other: &Color) -> u32 {
+        let dr = self.red as i32 - other.red as i32;
+        let dg = self.green as i32 - other.green as i32;
+        let db = self.blue as i32 - other.blue as i32;
+
+        (dr * dr + dg * dg + db * db) as u32
+    }
+
+    /// This color drawn over `under` with `opacity` from 0 (only `under`) to 1 (only this color).
+    pub fn blend(&self, under: &Color, opacity: f32) -> Color {
+        let mix = |a: u8, b: u8| (a as f32 * opacity + b as f32 * (1.0 - opacity)).round() as u8;
+        Color::rgb(mix(self.red, under.red), mix(self.green, under.green), mix(self.blue, under.blue))
+    }
+}
+
+/// The 16 ANSI colors as xterm draws them by default.
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Intensities of the 6x6x6 color cube of the 256 color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// How many colors the terminal can display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    /// 24-bit colors.
+    #[default]
+    TrueColor,
+    /// The xterm 256 color palette.
+    Ansi256,
+    /// The 16 basic ANSI colors.
+    Ansi16,
+    /// Only black and white.
+    Monochrome,
+}
+
+impl ColorDepth {
+    /// Guesses the color depth of the terminal from the `COLORTERM` and `TERM` environment
+    /// variables.
+    pub fn detect() -> ColorDepth {
+        ColorDepth::detect_from(
+            env::var("COLORTERM").ok().as_deref(),
+            env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    fn detect_from(colorterm: Option<&str>, term: Option<&str>) -> ColorDepth {
+        if let Some("truecolor" | "24bit") = colorterm {
+            return ColorDepth::TrueColor;
+        }
+
+        match term {
+            None | Some("" | "dumb") => ColorDepth::Monochrome,
+            Some(t) if t.ends_with("-direct") => ColorDepth::TrueColor,
+            Some(t) if t.contains("256color") => ColorDepth::Ansi256,
+            Some(_) => ColorDepth::Ansi16,
+        }
+    }
+
+    /// Index and value of the palette entry nearest to `color`, true colors are their own palette.
+    fn nearest(&self, color: &Color) -> (u8, Color) {
+        match self {
+            ColorDepth::TrueColor => (0, color.clone()),
+            ColorDepth::Ansi256 => {
+                let level = |c: u8| {
+                    (0..CUBE_LEVELS.len())
+                        .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - c as i32).abs())
+                        .unwrap()
+                };
+                let (r, g, b) = (level(color.red), level(color.green), level(color.blue));
+                let cube = Color::rgb(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+                let cube_index = (16 + 36 * r + 6 * g + b) as u8;
+
+                let average = (color.red as u32 + color.green as u32 + color.blue as u32) / 3;
+                let grey_step = (average.saturating_sub(3) / 10).min(23) as u8;
+                let grey = Color::grey(8 + 10 * grey_step);
+
+                if grey.distance(color) < cube.distance(color) {
+                    (232 + grey_step, grey)
+                } else {
+                    (cube_index, cube)
+                }
+            }
+            ColorDepth::Ansi16 => ANSI_16
+                .iter()
+                .enumerate()
+                .map(|(i, (r, g, b))| (i as u8, Color::rgb(*r, *g, *b)))
+                .min_by_key(|(_, c)| c.distance(color))
+                .unwrap(),
+            ColorDepth::Monochrome => {
+                let luma = (299 * color.red as u32 + 587 * color.green as u32 + 114 * color.blue as u32) / 1000;
+                if luma < 128 {
+                    (0, Color::black())
+                } else {
+                    (1, Color::white())
+                }
+            }
+        }
+    }
+
+    /// The color of this depth's palette that is nearest to `color`.
+    pub fn quantize(&self, color: &Color) -> Color {
+        self.nearest(color).1
+    }
 }
 
 impl Clone for Color {
@@ -113,6 +287,30 @@ impl Clone for Color {
 
 #[cfg(test)]
 mod tests {
+    use crate::{color::ColorDepth, Color};
+
+    #[test]
+    fn test_detect_color_depth() {
+        assert_eq!(ColorDepth::detect_from(Some("truecolor"), Some("xterm")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::detect_from(None, Some("tmux-256color")), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::detect_from(None, Some("xterm-direct")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::detect_from(None, Some("linux")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::detect_from(None, Some("dumb")), ColorDepth::Monochrome);
+    }
+
+    #[test]
+    fn test_quantize() {
+        assert_eq!(ColorDepth::Ansi256.quantize(&Color::rgb(250, 100, 2)), Color::rgb(255, 95, 0));
+        assert_eq!(ColorDepth::Ansi256.quantize(&Color::grey(100)), Color::grey(98));
+        assert_eq!(ColorDepth::Ansi16.quantize(&Color::rgb(10, 190, 30)), Color::rgb(0, 205, 0));
+        assert_eq!(ColorDepth::Monochrome.quantize(&Color::rgb(0, 0, 255)), Color::black());
+
+        let mut out: Vec<u8> = vec![];
+        Color::rgb(250, 100, 2).apply_fg_in(&mut out, ColorDepth::Ansi256).unwrap();
+        Color::rgb(250, 10, 10).apply_bg_in(&mut out, ColorDepth::Ansi16).unwrap();
+        assert_eq!(out, b"\x1b[38;5;202m\x1b[101m");
+    }
+
     #[test]
     fn test_color_to_c_str() {
         for i in 0..=255 {
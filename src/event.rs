@@ -1,87 +1,230 @@
-use crate::{profile, term_disable_stdio_buffer, term_read_char, term_reenable_stdio_buffer};
+use std::{
+    io::{self, stdout, Write},
+    time::{Duration, Instant},
+};
 
-#[derive(Debug)]
+use crate::{
+    backend,
+    framebuffer::RenderMode,
+    key::KeyEvent,
+    parser::{Parser, Reply, ESC_TIMEOUT},
+    profile,
+    term::{self, Terminal, Wakeup},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mouse {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Press,
+    Release,
+}
+
+/// Mouse positions are in framebuffer pixels, the top left pixel of the cell under the cursor.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
-    CharEvent(char),
-    // MouseButton(Mouse, Action),
-    // MouseMove(i32, i32),
-    // Scroll(i32, i32),
+    Key(KeyEvent),
+    /// A button was pressed or released at (x, y).
+    MouseButton(Mouse, Action, i64, i64),
+    /// The mouse moved to (x, y), only reported while dragging or with
+    /// [`MouseMode::AnyMotion`](crate::renderer::MouseMode::AnyMotion).
+    MouseMove(i64, i64),
+    /// Wheel ticks, (horizontal, vertical) with positive values to the right and up.
+    Scroll(i64, i64),
+    /// Text pasted into the terminal, it is not reported as key events.
+    Paste(String),
+    FocusGained,
+    FocusLost,
+    /// An input bound to the named action in [`Bindings`](crate::bindings::Bindings) was pressed
+    /// or released, follows the event of the input.
+    Action(String, Action),
+    /// New screen size in pixels.
     Resize(i64, i64),
 }
 
+/// How long to wait for the terminal to answer a query.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Kitty keyboard protocol flags: disambiguate escape codes, report event types, report alternate
+/// keys and report all keys as escape codes.
+const KEYBOARD_FLAGS: u32 = 1 | 2 | 4 | 8;
+
+/// Input is read in chunks of this size, a paste or a stream of mouse reports takes one read per
+/// chunk instead of one per byte.
+const READ_BUFFER_SIZE: usize = 1024;
+
 pub(crate) struct EventHandler {
+    terminal: Terminal,
+    wakeup: Wakeup,
+    parser: Parser,
+    /// Events that were read while waiting for the reply to a query.
+    queued: Vec<Event>,
+    mode: RenderMode,
     width: i64,
     height: i64,
 }
 
 impl EventHandler {
-    pub fn new() -> EventHandler {
+    /// Reads from stdin, or from `/dev/tty` if stdin is not a terminal.
+    pub fn new(mode: RenderMode) -> io::Result<EventHandler> {
         let (width, height) = if let Some((w, h)) = term_size::dimensions() {
             (w as i64, h as i64)
         } else {
             (0, 0)
         };
 
-        unsafe { term_disable_stdio_buffer() }; // My first ever unsafe code ^^
+        let terminal = match Terminal::stdin() {
+            Ok(t) => t,
+            Err(_) => Terminal::open_tty()?,
+        };
+        terminal.enable_cbreak()?;
+        let wakeup = Wakeup::new()?;
 
-        EventHandler { width, height }
+        Ok(EventHandler {
+            terminal,
+            wakeup,
+            parser: Parser::new(),
+            queued: vec![],
+            mode,
+            width,
+            height,
+        })
     }
 
-    fn char_event_get(&self) -> Vec<Event> {
+    fn key_event_get(&mut self) -> io::Result<Vec<Event>> {
         profile!();
-        let mut input: Vec<u8> = vec![];
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        let mut events = vec![];
 
-        let mut c = unsafe { term_read_char() };
-        if c == 0 {
-            return vec![];
+        // The parser is fed at least once, even without input, so a lone escape can time out.
+        loop {
+            let n = self.terminal.read(&mut buf)?;
+            events.extend(self.parser.feed(&buf[..n], Instant::now()));
+            if n < buf.len() {
+                break;
+            }
         }
-        input.push(c);
 
-        loop {
-            c = unsafe { term_read_char() };
-            if c == 0 {
+        Ok(events.into_iter().map(|e| self.to_pixels(e)).collect())
+    }
+
+    /// Sends `request` followed by a device attributes query, which every terminal answers, and
+    /// returns all replies up to that answer. Gives up after [`QUERY_TIMEOUT`], events read in the
+    /// meantime are reported by the next [`EventHandler::get_events`].
+    pub(crate) fn query(&mut self, request: &[u8]) -> io::Result<Vec<Reply>> {
+        profile!();
+        let mut out = stdout();
+        out.write_all(request)?;
+        out.write_all(b"\x1b[c")?;
+        out.flush()?;
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let mut replies = vec![];
+
+        while !replies.contains(&Reply::DeviceAttributes) {
+            let now = Instant::now();
+            if now >= deadline {
                 break;
             }
-            input.push(c);
+
+            term::wait_readable(&[self.terminal.fd()], Some(deadline - now))?;
+            let events = self.key_event_get()?;
+            self.queued.extend(events);
+            replies.extend(self.parser.take_replies());
+        }
+
+        Ok(replies)
+    }
+
+    /// Enables the kitty keyboard protocol if the terminal supports it, so key releases and
+    /// repeats are reported. Returns whether it is supported.
+    pub(crate) fn enable_keyboard_enhancement(&mut self) -> io::Result<bool> {
+        let replies = self.query(b"\x1b[?u")?;
+        if !replies.iter().any(|r| matches!(r, Reply::KeyboardFlags(_))) {
+            return Ok(false);
         }
 
-        String::from_utf8(input)
-            .expect("read() read non utf-8 byte")
-            .chars()
-            .filter(|c| *c != 1 as char)
-            .map(|c| Event::CharEvent(c))
-            .collect()
+        backend::push_keyboard_flags(KEYBOARD_FLAGS)?;
+        Ok(true)
+    }
+
+    /// Whether the terminal supports synchronized output (DEC private mode 2026), asked with
+    /// DECRQM.
+    pub(crate) fn supports_synchronized_output(&mut self) -> io::Result<bool> {
+        let replies = self.query(b"\x1b[?2026$p")?;
+        Ok(replies.iter().any(|r| r.supports_mode(2026)))
+    }
+
+    /// The parser reports mouse positions in cells, the app wants them in pixels.
+    fn to_pixels(&self, event: Event) -> Event {
+        let (cw, ch) = self.mode.cell_size();
+        let (cw, ch) = (cw as i64, ch as i64);
+
+        match event {
+            Event::MouseButton(b, a, x, y) => Event::MouseButton(b, a, x * cw, y * ch),
+            Event::MouseMove(x, y) => Event::MouseMove(x * cw, y * ch),
+            e => e,
+        }
     }
 
+    /// Only asks for the terminal size after a SIGWINCH, all signals since the last call result in
+    /// at most one event.
     fn resize_event_get(&mut self) -> Option<Event> {
+        if !self.wakeup.take_resized() {
+            return None;
+        }
+
         if let Some((w, h)) = term_size::dimensions() {
             let w = w as i64;
             let h = h as i64;
             if self.width != w || self.height != h {
                 self.width = w;
                 self.height = h;
-                Some(Event::Resize(w, h))
+
+                let (w, h) = self.mode.pixel_size(w as usize, h as usize);
+                Some(Event::Resize(w as i64, h as i64))
             } else {
                 None
             }
         } else {
-        None
+            None
         }
     }
 
-    pub(crate) fn get_events(&mut self) -> Vec<Event> {
-        let mut events = self.char_event_get();
+    /// Blocks until there is input, the terminal was resized, [`term::wake`] was called or the
+    /// timeout passed.
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> io::Result<()> {
+        // A lone escape byte becomes an Esc event only after its timeout.
+        let timeout = match (timeout, self.parser.has_pending()) {
+            (Some(t), true) => Some(t.min(ESC_TIMEOUT)),
+            (None, true) => Some(ESC_TIMEOUT),
+            (t, false) => t,
+        };
+
+        term::wait_readable(&[self.terminal.fd(), self.wakeup.fd()], timeout)
+    }
+
+    pub(crate) fn get_events(&mut self) -> io::Result<Vec<Event>> {
+        let mut events = std::mem::take(&mut self.queued);
+        events.extend(self.key_event_get()?);
+        // Replies to queries that timed out.
+        self.parser.take_replies();
 
         if let Some(e) = self.resize_event_get() {
             events.push(e);
         }
 
-        events
+        Ok(events)
     }
 }
 
 impl Drop for EventHandler {
     fn drop(&mut self) {
-        unsafe { term_reenable_stdio_buffer() };
+        backend::pop_keyboard_flags();
     }
 }
@@ -5,10 +5,81 @@ use std::{
 
 use image::{GenericImageView, Pixel};
 
-use crate::{profile, Color};
+use crate::{color::ColorDepth, glyph, profile, Color};
 
 const PIXEL_WIDTH: usize = 1;
-const PIXEL: &[u8] = b" ";
+
+/// How framebuffer pixels are mapped onto terminal cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// One pixel per cell, drawn as a colored space.
+    #[default]
+    Full,
+    /// Two vertically stacked pixels per cell, drawn with the upper half block `▀`.
+    HalfBlock,
+    /// 2x4 pixels per cell, drawn with braille patterns. Every pixel that is not black is a raised
+    /// dot, all dots of a cell share one color.
+    Braille,
+    /// 2x2 pixels per cell, drawn with the quadrant block that best approximates them using two
+    /// colors.
+    Quadrant,
+    /// 2x3 pixels per cell, drawn with the sextant block that best approximates them using two
+    /// colors.
+    Sextant,
+}
+
+impl RenderMode {
+    /// Number of pixels (horizontal, vertical) that make up one terminal cell.
+    pub fn cell_size(&self) -> (usize, usize) {
+        match self {
+            RenderMode::Full => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+            RenderMode::Quadrant => (2, 2),
+            RenderMode::Sextant => (2, 3),
+        }
+    }
+
+    /// Size in pixels of an area of `columns` x `rows` terminal cells.
+    pub fn pixel_size(&self, columns: usize, rows: usize) -> (usize, usize) {
+        let (cw, ch) = self.cell_size();
+        (columns / PIXEL_WIDTH * cw, rows * ch)
+    }
+}
+
+/// Offsets of the dots of a braille pattern, in the order of their bits.
+const BRAILLE_DOTS: [(usize, usize); 8] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (0, 3),
+    (1, 3),
+];
+
+/// How [`Framebuffer::new_dithered`] spreads the error of reducing colors to a palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dithering {
+    /// Every pixel is mapped to its nearest palette color.
+    None,
+    /// A 4x4 Bayer matrix, stable between frames and cheap.
+    Ordered,
+    /// Floyd–Steinberg error diffusion, looks best for still images.
+    FloydSteinberg,
+}
+
+/// 4x4 Bayer threshold matrix, values from 0 to 15.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// What a single terminal cell shows.
+#[derive(Debug, PartialEq)]
+struct Cell {
+    glyph: char,
+    fg: Color,
+    bg: Color,
+}
 
 #[derive(Debug)]
 pub enum FramebufferError {
@@ -18,6 +89,37 @@ pub enum FramebufferError {
     ImageError(image::ImageError),
 }
 
+/// Calls `plot` for every point of the line from `(x0, y0)` to `(x1, y1)` (Bresenham).
+pub(crate) fn line_points<F: FnMut(i64, i64)>(x0: i64, y0: i64, x1: i64, y1: i64, mut plot: F) {
+    let dx = (x1 - x0).abs();
+    let dy = (y1 - y0).abs();
+
+    let mut x = x0;
+    let mut y = y0;
+
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let mut err = if dx > dy { dx } else { -dy } / 2;
+    let mut e2;
+
+    loop {
+        plot(x, y);
+        if x == x1 && y == y1 {
+            break;
+        }
+        e2 = err;
+        if e2 > -dx {
+            err -= dy;
+            x += sx;
+        }
+        if e2 < dy {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Framebuffer {
     colors: Vec<Color>,
     width: usize,
@@ -40,10 +142,11 @@ impl Framebuffer {
         }
     }
 
-    pub fn new_terminal_size(color: Color) -> Result<Framebuffer, FramebufferError> {
+    pub fn new_terminal_size(mode: RenderMode, color: Color) -> Result<Framebuffer, FramebufferError> {
         profile!();
         if let Some((w, h)) = term_size::dimensions() {
-            Ok(Framebuffer::new(w / PIXEL_WIDTH, h, color))
+            let (w, h) = mode.pixel_size(w, h);
+            Ok(Framebuffer::new(w, h, color))
         } else {
             Err(FramebufferError::CantGetTerminalSize)
         }
@@ -155,6 +258,88 @@ impl Framebuffer {
         }
     }
 
+    /// Reduces the colors of `old` to the palette of `depth`, the result is displayed identically
+    /// by terminals with that color depth.
+    pub fn new_dithered(old: &Framebuffer, depth: ColorDepth, dithering: Dithering) -> Framebuffer {
+        profile!();
+
+        let (width, height) = (old.width, old.height);
+        let mut colors: Vec<Color> = Vec::with_capacity(height * width);
+
+        // Roughly the distance between two neighbouring palette colors.
+        let spread = match depth {
+            ColorDepth::TrueColor => 0.0,
+            ColorDepth::Ansi256 => 40.0,
+            ColorDepth::Ansi16 => 128.0,
+            ColorDepth::Monochrome => 255.0,
+        };
+
+        let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+
+        match dithering {
+            Dithering::None | Dithering::Ordered => {
+                for j in 0..height {
+                    for i in 0..width {
+                        let c = &old.colors[j * width + i];
+
+                        let offset = if dithering == Dithering::Ordered {
+                            (BAYER[j % 4][i % 4] as f32 + 0.5) / 16.0 - 0.5
+                        } else {
+                            0.0
+                        } * spread;
+
+                        let c = Color::rgb(
+                            clamp(c.red as f32 + offset),
+                            clamp(c.green as f32 + offset),
+                            clamp(c.blue as f32 + offset),
+                        );
+                        colors.push(depth.quantize(&c));
+                    }
+                }
+            }
+            Dithering::FloydSteinberg => {
+                let mut error: Vec<[f32; 3]> = vec![[0.0; 3]; width * height];
+
+                for j in 0..height {
+                    for i in 0..width {
+                        let c = &old.colors[j * width + i];
+                        let e = error[j * width + i];
+
+                        let wanted = [c.red as f32 + e[0], c.green as f32 + e[1], c.blue as f32 + e[2]];
+                        let q = depth.quantize(&Color::rgb(clamp(wanted[0]), clamp(wanted[1]), clamp(wanted[2])));
+                        let diff = [
+                            wanted[0] - q.red as f32,
+                            wanted[1] - q.green as f32,
+                            wanted[2] - q.blue as f32,
+                        ];
+
+                        let mut spread_error = |x: usize, y: usize, weight: f32| {
+                            if x < width && y < height {
+                                for k in 0..3 {
+                                    error[y * width + x][k] += diff[k] * weight;
+                                }
+                            }
+                        };
+                        spread_error(i + 1, j, 7.0 / 16.0);
+                        if i > 0 {
+                            spread_error(i - 1, j + 1, 3.0 / 16.0);
+                        }
+                        spread_error(i, j + 1, 5.0 / 16.0);
+                        spread_error(i + 1, j + 1, 1.0 / 16.0);
+
+                        colors.push(q);
+                    }
+                }
+            }
+        }
+
+        Framebuffer {
+            colors,
+            width,
+            height,
+        }
+    }
+
     pub fn clear(&mut self, color: Color) {
         profile!();
         for i in 0..self.colors.len() {
@@ -202,32 +387,9 @@ impl Framebuffer {
     }
 
     pub fn line(&mut self, x0: i64, y0: i64, x1: i64, y1: i64, color: Color) {
-        let dx = (x1 - x0).abs();
-        let dy = (y1 - y0).abs();
-
-        let mut x = x0;
-        let mut y = y0;
-
-        let sx = if x0 < x1 { 1 } else { -1 };
-        let sy = if y0 < y1 { 1 } else { -1 };
-        let mut err = if dx > dy { dx } else { -dy } / 2;
-        let mut e2;
-
-        loop {
+        line_points(x0, y0, x1, y1, |x, y| {
             self.pixel(x, y, color.clone());
-            if x == x1 && y == y1 {
-                break;
-            }
-            e2 = err;
-            if e2 > -dx {
-                err -= dy;
-                x += sx;
-            }
-            if e2 < dy {
-                err += dx;
-                y += sy;
-            }
-        }
+        });
     }
 
     pub(crate) fn hide_cursor<R>(&self, out: &mut R, hide: bool) -> Result<(), FramebufferError>
@@ -246,59 +408,223 @@ impl Framebuffer {
         Ok(())
     }
 
-    pub(crate) fn reset_cursor<R>(&self, out: &mut R) -> Result<(), FramebufferError>
+    pub(crate) fn alternate_screen<R>(&self, out: &mut R, enable: bool) -> Result<(), FramebufferError>
     where
         R: std::io::Write,
     {
-        match out.write(format!("\x1b[{}F", self.height() - 1).as_bytes()) {
+        match if enable {
+            out.write_all(b"\x1b[?1049h")
+        } else {
+            out.write_all(b"\x1b[?1049l")
+        } {
             Ok(_) => (),
             Err(e) => return Err(FramebufferError::IoError(e)),
-        }
+        };
 
         Ok(())
     }
 
-    pub(crate) fn render<R>(&self, out: &mut R) -> Result<(), FramebufferError>
+    fn columns(&self, mode: RenderMode) -> usize {
+        self.width.div_ceil(mode.cell_size().0)
+    }
+
+    fn rows(&self, mode: RenderMode) -> usize {
+        self.height.div_ceil(mode.cell_size().1)
+    }
+
+    /// Color of a pixel, pixels outside of the framebuffer are black.
+    fn color_at(&self, x: usize, y: usize) -> Color {
+        if x < self.width && y < self.height {
+            self.colors[y * self.width + x].clone()
+        } else {
+            Color::black()
+        }
+    }
+
+    fn cell(&self, mode: RenderMode, column: usize, row: usize) -> Cell {
+        let (cw, ch) = mode.cell_size();
+        let (x, y) = (column * cw, row * ch);
+
+        match mode {
+            RenderMode::Full => {
+                let c = self.color_at(x, y);
+                Cell {
+                    glyph: ' ',
+                    fg: c.clone(),
+                    bg: c,
+                }
+            }
+            RenderMode::HalfBlock => {
+                let top = self.color_at(x, y);
+                let bottom = self.color_at(x, y + 1);
+                Cell {
+                    glyph: if top == bottom { ' ' } else { '▀' },
+                    fg: top,
+                    bg: bottom,
+                }
+            }
+            RenderMode::Braille => {
+                let mut dots: u32 = 0;
+                let mut lit: Vec<Color> = Vec::with_capacity(BRAILLE_DOTS.len());
+
+                for (bit, (dx, dy)) in BRAILLE_DOTS.iter().enumerate() {
+                    let c = self.color_at(x + dx, y + dy);
+                    if c != Color::black() {
+                        dots |= 1 << bit;
+                        lit.push(c);
+                    }
+                }
+
+                // The most common color of the lit pixels, so crossing lines keep their colors
+                // instead of being mixed.
+                let fg = lit
+                    .iter()
+                    .max_by_key(|c| lit.iter().filter(|o| o == c).count())
+                    .cloned()
+                    .unwrap_or(Color::black());
+
+                Cell {
+                    glyph: if dots == 0 {
+                        ' '
+                    } else {
+                        char::from_u32(0x2800 + dots).unwrap()
+                    },
+                    fg,
+                    bg: Color::black(),
+                }
+            }
+            RenderMode::Quadrant | RenderMode::Sextant => {
+                let mut pixels: Vec<Color> = Vec::with_capacity(cw * ch);
+                for dy in 0..ch {
+                    for dx in 0..cw {
+                        pixels.push(self.color_at(x + dx, y + dy));
+                    }
+                }
+
+                let (mask, fg, bg) = glyph::fit_two_colors(&pixels);
+
+                Cell {
+                    glyph: if mode == RenderMode::Quadrant {
+                        glyph::quadrant(mask)
+                    } else {
+                        glyph::sextant(mask)
+                    },
+                    fg,
+                    bg,
+                }
+            }
+        }
+    }
+
+    fn cell_changed(&self, prev: &Framebuffer, mode: RenderMode, column: usize, row: usize) -> bool {
+        let (cw, ch) = mode.cell_size();
+
+        for y in (row * ch)..min((row + 1) * ch, self.height) {
+            for x in (column * cw)..min((column + 1) * cw, self.width) {
+                if self.colors[y * self.width + x] != prev.colors[y * self.width + x] {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Writes a single cell in the colors of `depth`, only emitting the colors that differ from
+    /// the current `fg`/`bg`.
+    fn write_cell<R>(
+        out: &mut R,
+        cell: &Cell,
+        depth: ColorDepth,
+        fg: &mut Option<Color>,
+        bg: &mut Option<Color>,
+    ) -> Result<(), FramebufferError>
     where
         R: std::io::Write,
     {
-        profile!();
-        let mut current: Option<&Color> = None;
-
-        let mut x = 0;
-        let mut y = 0;
-        for c in &self.colors {
-            if current == None || current.unwrap() != c {
-                current = Some(c);
-                match c.apply(out) {
-                    Ok(_) => (),
-                    Err(e) => return Err(FramebufferError::IoError(e)),
-                }
+        let cell_fg = depth.quantize(&cell.fg);
+        let cell_bg = depth.quantize(&cell.bg);
+        let glyph = if cell_fg == cell_bg { ' ' } else { cell.glyph };
+
+        if glyph != ' ' && fg.as_ref() != Some(&cell_fg) {
+            match cell_fg.apply_fg_in(out, depth) {
+                Ok(_) => (),
+                Err(e) => return Err(FramebufferError::IoError(e)),
             }
-            match out.write(PIXEL) {
+            *fg = Some(cell_fg);
+        }
+        if bg.as_ref() != Some(&cell_bg) {
+            match cell_bg.apply_bg_in(out, depth) {
                 Ok(_) => (),
                 Err(e) => return Err(FramebufferError::IoError(e)),
             }
+            *bg = Some(cell_bg);
+        }
 
-            x += 1;
+        let mut buf = [0; 4];
+        match out.write_all(glyph.encode_utf8(&mut buf).as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(FramebufferError::IoError(e)),
+        }
+    }
 
-            if x == self.width {
-                y += 1;
+    /// Moves the cursor to the cell in `column` and `row`.
+    fn move_cursor<R>(out: &mut R, column: usize, row: usize) -> Result<(), FramebufferError>
+    where
+        R: std::io::Write,
+    {
+        match out.write_all(format!("\x1b[{};{}H", row + 1, column * PIXEL_WIDTH + 1).as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(FramebufferError::IoError(e)),
+        }
+    }
 
-                current = None;
-                match Color::reset(out) {
-                    Ok(_) => (),
-                    Err(e) => return Err(FramebufferError::IoError(e)),
-                };
-                if y != self.height() {
-                    match out.write(b"\n") {
-                        Ok(_) => (),
-                        Err(e) => return Err(FramebufferError::IoError(e)),
+    /// Writes the framebuffer to `out`, the top-left cell is drawn in the top-left corner of the
+    /// screen.
+    ///
+    /// If `prev` is the frame that is currently on screen, only the cells that differ from it
+    /// are written, otherwise the whole framebuffer is.
+    pub(crate) fn render<R>(
+        &self,
+        out: &mut R,
+        mode: RenderMode,
+        depth: ColorDepth,
+        prev: Option<&Framebuffer>,
+    ) -> Result<(), FramebufferError>
+    where
+        R: std::io::Write,
+    {
+        profile!();
+        let prev = prev.filter(|p| p.width == self.width && p.height == self.height);
+        let (columns, rows) = (self.columns(mode), self.rows(mode));
+
+        let mut fg = None;
+        let mut bg = None;
+        let mut cursor = None;
+
+        for y in 0..rows {
+            for x in 0..columns {
+                if let Some(prev) = prev {
+                    if !self.cell_changed(prev, mode, x, y) {
+                        continue;
                     }
                 }
+
+                if cursor != Some((x, y)) {
+                    Framebuffer::move_cursor(out, x, y)?;
+                }
+                Framebuffer::write_cell(out, &self.cell(mode, x, y), depth, &mut fg, &mut bg)?;
+
+                cursor = Some((x + 1, y));
             }
         }
 
+        if fg.is_some() || bg.is_some() {
+            match Color::reset(out) {
+                Ok(_) => (),
+                Err(e) => return Err(FramebufferError::IoError(e)),
+            };
+        }
+
         match out.flush() {
             Ok(_) => (),
             Err(e) => return Err(FramebufferError::IoError(e)),
@@ -319,3 +645,90 @@ impl Framebuffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        color::ColorDepth,
+        framebuffer::{Dithering, RenderMode},
+        Color, Framebuffer,
+    };
+
+    #[test]
+    fn test_render_diff_only_writes_changed_pixels() {
+        let prev = Framebuffer::new(4, 3, Color::black());
+        let mut fb = Framebuffer::new(4, 3, Color::black());
+
+        let mut out: Vec<u8> = vec![];
+        fb.render(&mut out, RenderMode::Full, ColorDepth::TrueColor, Some(&prev)).unwrap();
+        assert!(out.is_empty());
+
+        fb.pixel(2, 1, Color::red());
+        let mut out: Vec<u8> = vec![];
+        fb.render(&mut out, RenderMode::Full, ColorDepth::TrueColor, Some(&prev)).unwrap();
+
+        let mut expected: Vec<u8> = b"\x1b[2;3H".to_vec();
+        Color::red().apply_bg(&mut expected).unwrap();
+        expected.extend_from_slice(b" \x1b[0m");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_render_half_block() {
+        let mut fb = Framebuffer::new(2, 2, Color::black());
+        fb.pixel(0, 0, Color::red());
+
+        let mut out: Vec<u8> = vec![];
+        fb.render(&mut out, RenderMode::HalfBlock, ColorDepth::TrueColor, None).unwrap();
+
+        let mut expected: Vec<u8> = b"\x1b[1;1H".to_vec();
+        Color::red().apply_fg(&mut expected).unwrap();
+        Color::black().apply_bg(&mut expected).unwrap();
+        expected.extend_from_slice("▀ \x1b[0m".as_bytes());
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_render_braille() {
+        let mut fb = Framebuffer::new(2, 4, Color::black());
+        fb.line(0, 0, 1, 3, Color::green());
+        fb.pixel(1, 0, Color::red());
+
+        let mut out: Vec<u8> = vec![];
+        fb.render(&mut out, RenderMode::Braille, ColorDepth::TrueColor, None).unwrap();
+
+        let mut expected: Vec<u8> = b"\x1b[1;1H".to_vec();
+        Color::green().apply_fg(&mut expected).unwrap();
+        Color::black().apply_bg(&mut expected).unwrap();
+        expected.extend_from_slice("⢫\x1b[0m".as_bytes());
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_render_sextant() {
+        let mut fb = Framebuffer::new(2, 3, Color::rgb(0, 0, 200));
+        fb.pixel(0, 0, Color::rgb(250, 0, 0));
+        fb.pixel(1, 1, Color::rgb(240, 0, 0));
+        fb.pixel(1, 2, Color::rgb(5, 0, 200));
+
+        let mut out: Vec<u8> = vec![];
+        fb.render(&mut out, RenderMode::Sextant, ColorDepth::TrueColor, None).unwrap();
+
+        let mut expected: Vec<u8> = b"\x1b[1;1H".to_vec();
+        Color::rgb(245, 0, 0).apply_fg(&mut expected).unwrap();
+        Color::rgb(1, 0, 200).apply_bg(&mut expected).unwrap();
+        expected.extend_from_slice("\u{1FB08}\x1b[0m".as_bytes());
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_dithering_keeps_average_brightness() {
+        let fb = Framebuffer::new(8, 8, Color::grey(128));
+
+        for dithering in [Dithering::Ordered, Dithering::FloydSteinberg] {
+            let dithered = Framebuffer::new_dithered(&fb, ColorDepth::Monochrome, dithering);
+            let white = dithered.colors.iter().filter(|c| **c == Color::white()).count();
+            assert!((28..=36).contains(&white), "{:?}: {} white pixels", dithering, white);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::Color;
+
+/// Quadrant glyphs indexed by their mask, bit 0 is the upper left quarter, bit 1 the upper right,
+/// bit 2 the lower left and bit 3 the lower right.
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// Glyph that fills the quarters of a cell set in `mask`.
+pub(crate) fn quadrant(mask: u32) -> char {
+    QUADRANTS[mask as usize]
+}
+
+/// Glyph that fills the sixths of a cell set in `mask`, bit `2 * y + x` is the sixth in column `x`
+/// and row `y`.
+pub(crate) fn sextant(mask: u32) -> char {
+    match mask {
+        0 => ' ',
+        21 => '▌',
+        42 => '▐',
+        63 => '█',
+        // The sextant block skips the four patterns above that already exist elsewhere.
+        _ => {
+            let skipped = (mask > 21) as u32 + (mask > 42) as u32;
+            char::from_u32(0x1FB00 + mask - 1 - skipped).unwrap()
+        }
+    }
+}
+
+fn mean<'a, I>(colors: I) -> Color
+where
+    I: Iterator<Item = &'a Color>,
+{
+    let mut r: u32 = 0;
+    let mut g: u32 = 0;
+    let mut b: u32 = 0;
+    let mut n = 0;
+
+    for c in colors {
+        r += c.red as u32;
+        g += c.green as u32;
+        b += c.blue as u32;
+        n += 1;
+    }
+
+    if n == 0 {
+        return Color::black();
+    }
+
+    Color::rgb((r / n) as u8, (g / n) as u8, (b / n) as u8)
+}
+
+/// Splits `pixels` into a foreground and a background group so that the summed squared error of
+/// drawing each group with its mean color is minimal.
+///
+/// Returns the mask of the foreground pixels, the foreground and the background color.
+pub(crate) fn fit_two_colors(pixels: &[Color]) -> (u32, Color, Color) {
+    let mut best = (0, Color::black(), Color::black());
+    let mut best_error = u32::MAX;
+
+    // A mask and its complement only swap the colors, so the highest pixel is always background.
+    for mask in 0..(1u32 << (pixels.len() - 1)) {
+        let in_mask = |i: &usize| mask & (1 << i) != 0;
+
+        let fg = mean((0..pixels.len()).filter(in_mask).map(|i| &pixels[i]));
+        let bg = mean((0..pixels.len()).filter(|i| !in_mask(i)).map(|i| &pixels[i]));
+
+        let error = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| p.distance(if in_mask(&i) { &fg } else { &bg }))
+            .sum();
+
+        if error < best_error {
+            best_error = error;
+            best = (mask, fg, bg);
+        }
+    }
+
+    best
+}
@@ -0,0 +1,461 @@
+use std::time::Duration;
+
+use crate::{
+    app::{App, AppError, ExitStatus, Scene},
+    backend::MemoryBackend,
+    bindings::Bindings,
+    color::ColorDepth,
+    event::Event,
+    framebuffer::RenderMode,
+    recording::Recording,
+    renderer::RendererOptions,
+    time::Timestep,
+    profile, Framebuffer, Renderer,
+};
+
+/// Configuration of a [`run_headless`] run.
+pub struct HeadlessConfig {
+    /// Width of the simulated terminal in cells.
+    pub width: usize,
+    /// Height of the simulated terminal in cells.
+    pub height: usize,
+    pub render_mode: RenderMode,
+    pub color_depth: ColorDepth,
+    pub renderer_options: RendererOptions,
+    /// Number of times `Scene::update` is called.
+    pub frames: usize,
+    /// Events and the index of the frame they are delivered in, before that frame's update.
+    pub events: Vec<(usize, Event)>,
+    /// Keep a copy of every frame instead of only the last one.
+    pub capture_frames: bool,
+    /// Bindings that turn the scripted events into action events.
+    pub bindings: Bindings,
+    /// How long every frame pretends to take.
+    pub frame_time: Duration,
+    /// How long each of the first frames takes instead of `frame_time`, e.g. the recorded frame
+    /// times of a replay.
+    pub frame_times: Vec<Duration>,
+    pub timestep: Timestep,
+    /// Pretends the terminal supports the kitty keyboard protocol: key releases are tracked and a
+    /// Ctrl-C key event interrupts the run.
+    pub keyboard_enhancement: bool,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        HeadlessConfig {
+            width: 80,
+            height: 24,
+            render_mode: RenderMode::Full,
+            color_depth: ColorDepth::TrueColor,
+            renderer_options: RendererOptions::default(),
+            frames: 1,
+            events: vec![],
+            capture_frames: false,
+            bindings: Bindings::new(),
+            frame_time: Duration::from_secs(1) / 60,
+            frame_times: vec![],
+            timestep: Timestep::Variable,
+            keyboard_enhancement: false,
+        }
+    }
+}
+
+impl HeadlessConfig {
+    /// Replays `recording`: runs as many frames as were recorded with the recorded frame times and
+    /// delivers every event in the frame it was recorded in. The terminal size and render mode
+    /// are the recorded ones, if the recording has them.
+    pub fn with_recording(self, recording: &Recording) -> HeadlessConfig {
+        let (width, height, render_mode) = match recording.header {
+            Some(h) => (h.width, h.height, h.render_mode),
+            None => (self.width, self.height, self.render_mode),
+        };
+        HeadlessConfig {
+            width,
+            height,
+            render_mode,
+            frames: recording.frames,
+            frame_times: recording.frame_times.clone(),
+            events: recording.events.iter().map(|e| (e.frame, e.event.clone())).collect(),
+            ..self
+        }
+    }
+}
+
+/// What a scene drew during a [`run_headless`] run.
+pub struct HeadlessOutput {
+    /// The last frame.
+    pub framebuffer: Framebuffer,
+    /// Every frame in order, empty unless `capture_frames` was set.
+    pub frames: Vec<Framebuffer>,
+    /// Everything the renderer would have written to the terminal.
+    pub output: Vec<u8>,
+    /// Why the app stopped before the configured number of frames, `None` if it didn't.
+    pub exit: Option<ExitStatus>,
+}
+
+/// Runs `scene` without a terminal: attaches it, calls `update` for the configured number of
+/// frames (or until it quits) with the scripted events and detaches it again.
+///
+/// Nothing touches the real terminal, so scenes can be tested with `cargo test`.
+pub fn run_headless(scene: Box<dyn Scene>, config: HeadlessConfig) -> Result<HeadlessOutput, AppError> {
+    profile!();
+    let backend = MemoryBackend::new(config.width, config.height);
+    let mut renderer = match Renderer::with_backend(
+        Box::new(backend.clone()),
+        config.render_mode,
+        config.color_depth,
+    ) {
+        Ok(renderer) => renderer,
+        Err(e) => return Err(AppError::RendererError(e)),
+    };
+    renderer.set_options(config.renderer_options);
+
+    let mut events: Vec<Vec<Event>> = (0..config.frames).map(|_| vec![]).collect();
+    for (frame, event) in config.events {
+        if let Some(e) = events.get_mut(frame) {
+            e.push(event);
+        }
+    }
+
+    let mut app = App::headless(
+        scene,
+        renderer,
+        config.bindings,
+        config.timestep,
+        config.frame_time,
+        &config.frame_times,
+        config.keyboard_enhancement,
+    );
+    let mut frames = vec![];
+    let mut framebuffer = None;
+
+    app.attach()?;
+
+    for events in &events {
+        app.update(events)?;
+        if app.exit_status().is_some() {
+            break;
+        }
+
+        let fb = app.app_info().renderer.borrow().composited();
+        if config.capture_frames {
+            frames.push(fb.clone());
+        }
+        framebuffer = Some(fb);
+
+        app.present()?;
+    }
+
+    let exit = app.exit_status();
+    app.detach()?;
+
+    let framebuffer = match framebuffer {
+        Some(fb) => fb,
+        None => app.app_info().renderer.borrow().composited(),
+    };
+
+    Ok(HeadlessOutput {
+        framebuffer,
+        frames,
+        output: backend.take(),
+        exit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        app::{AppError, AppInfo, ExitStatus, Scene, SceneCommand, SceneError},
+        bindings::{Bindings, Input},
+        event::{Action, Event},
+        framebuffer::RenderMode,
+        headless::{run_headless, HeadlessConfig},
+        key::{KeyCode, KeyEvent, Modifiers},
+        parser::Parser,
+        recording::Recording,
+        time::{Time, Timestep},
+        Color, Renderer,
+    };
+
+    struct DotScene {
+        x: i64,
+        attached: bool,
+    }
+
+    impl Scene for DotScene {
+        fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
+            assert!(self.attached);
+            renderer.pixel(self.x, 1, Color::red());
+            Ok(SceneCommand::None)
+        }
+
+        fn event(&mut self, event: &Event) -> Result<SceneCommand, SceneError> {
+            if let Event::Key(KeyEvent {
+                code: KeyCode::Char('d'),
+                ..
+            }) = event
+            {
+                self.x += 1;
+            }
+
+            match event {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('p'),
+                    ..
+                }) => Ok(SceneCommand::Push(Box::new(Overlay))),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => Ok(SceneCommand::Pop),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('q'),
+                    ..
+                }) => Ok(SceneCommand::Quit(3)),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('e'),
+                    ..
+                }) => Err("broken".into()),
+                Event::Action(a, Action::Press) if a == "pause" => Ok(SceneCommand::Push(Box::new(Overlay))),
+                _ => Ok(SceneCommand::None),
+            }
+        }
+
+        fn attach(&mut self, _app_info: &AppInfo) -> Result<(), SceneError> {
+            self.attached = true;
+            Ok(())
+        }
+
+        fn detach(&mut self) -> Result<(), SceneError> {
+            Ok(())
+        }
+    }
+
+    struct Overlay;
+
+    impl Scene for Overlay {
+        fn update(&mut self, renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
+            renderer.pixel(0, 3, Color::green());
+            Ok(SceneCommand::None)
+        }
+
+        fn event(&mut self, _event: &Event) -> Result<SceneCommand, SceneError> {
+            Ok(SceneCommand::Pop)
+        }
+
+        fn attach(&mut self, _app_info: &AppInfo) -> Result<(), SceneError> {
+            Ok(())
+        }
+
+        fn detach(&mut self) -> Result<(), SceneError> {
+            Ok(())
+        }
+
+        fn is_overlay(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let config = HeadlessConfig {
+            width: 8,
+            height: 4,
+            frames: 3,
+            events: vec![
+                (1, Event::Key(KeyCode::Char('d').into())),
+                (2, Event::Key(KeyCode::Char('d').into())),
+            ],
+            capture_frames: true,
+            ..Default::default()
+        };
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+
+        assert_eq!(out.frames.len(), 3);
+        for (i, frame) in out.frames.iter().enumerate() {
+            assert_eq!(frame.get_pixel(i as i64, 1).unwrap(), Color::red());
+        }
+        assert_eq!(out.framebuffer.get_pixel(2, 1).unwrap(), Color::red());
+        assert_eq!(out.framebuffer.get_pixel(1, 1).unwrap(), Color::black());
+        assert!(!out.output.is_empty());
+    }
+
+    #[test]
+    fn test_replay_recording() {
+        let recording = Recording::from_text("1 16000 key c:100 0 press\n1 16000 key c:100 0 press\n4 64000 end\n").unwrap();
+        let config = HeadlessConfig {
+            width: 8,
+            height: 4,
+            ..Default::default()
+        }
+        .with_recording(&recording);
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+
+        assert_eq!(out.framebuffer.get_pixel(2, 1).unwrap(), Color::red());
+        assert_eq!(out.framebuffer.get_pixel(0, 1).unwrap(), Color::black());
+    }
+
+    #[test]
+    fn test_replay_uses_recorded_screen() {
+        let recording = Recording::from_text("0 0 start 6 3 halfblock\n1 16000 key c:100 0 press\n2 32000 end\n").unwrap();
+        let config = HeadlessConfig::default().with_recording(&recording);
+        assert_eq!((config.width, config.height, config.render_mode), (6, 3, RenderMode::HalfBlock));
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+
+        assert_eq!((out.framebuffer.width(), out.framebuffer.height()), (6, 6));
+        assert_eq!(out.framebuffer.get_pixel(1, 1).unwrap(), Color::red());
+    }
+
+    #[test]
+    fn test_scene_stack() {
+        let key = |c| Event::Key(KeyCode::Char(c).into());
+        let config = HeadlessConfig {
+            width: 8,
+            height: 4,
+            frames: 10,
+            events: vec![(0, key('p')), (1, key('d')), (2, key('d')), (3, Event::Key(KeyCode::Esc.into()))],
+            capture_frames: true,
+            ..Default::default()
+        };
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+
+        // The overlay is drawn on top of the dot and swallows the first 'd' when popped.
+        assert_eq!(out.frames[0].get_pixel(0, 1).unwrap(), Color::red());
+        assert_eq!(out.frames[0].get_pixel(0, 3).unwrap(), Color::green());
+        assert_eq!(out.frames[1].get_pixel(0, 3).unwrap(), Color::black());
+        assert_eq!(out.frames[2].get_pixel(1, 1).unwrap(), Color::red());
+        // Popping the last scene ends the run.
+        assert_eq!(out.frames.len(), 3);
+        assert_eq!(out.exit, Some(ExitStatus::Finished));
+    }
+
+    #[test]
+    fn test_quit_and_errors() {
+        let key = |c| Event::Key(KeyCode::Char(c).into());
+        let config = HeadlessConfig {
+            frames: 10,
+            events: vec![(2, key('q'))],
+            capture_frames: true,
+            ..Default::default()
+        };
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+        assert_eq!(out.frames.len(), 2);
+        assert_eq!(out.exit, Some(ExitStatus::Quit(3)));
+        assert_eq!(out.exit.unwrap().code(), 3);
+
+        let config = HeadlessConfig {
+            frames: 10,
+            events: vec![(1, key('e'))],
+            ..Default::default()
+        };
+
+        match run_headless(Box::new(DotScene { x: 0, attached: false }), config) {
+            Err(AppError::SceneError(e)) => assert_eq!(e.to_string(), "broken"),
+            _ => panic!("expected a scene error"),
+        }
+    }
+
+    #[test]
+    fn test_bound_key_pops_overlay() {
+        let key = |c| Event::Key(KeyCode::Char(c).into());
+        let mut bindings = Bindings::new();
+        bindings.bind("pause", Input::key(KeyCode::Char('x'), Modifiers::NONE)).unwrap();
+        let config = HeadlessConfig {
+            width: 8,
+            height: 4,
+            frames: 3,
+            events: vec![(0, key('x')), (1, key('x'))],
+            capture_frames: true,
+            bindings,
+            ..Default::default()
+        };
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config).unwrap();
+
+        // The overlay pops itself on the key, its action must not reach the scene below and
+        // pause again.
+        assert_eq!(out.frames[0].get_pixel(0, 3).unwrap(), Color::green());
+        assert_eq!(out.frames[1].get_pixel(0, 3).unwrap(), Color::black());
+        assert_eq!(out.frames[2].get_pixel(0, 3).unwrap(), Color::black());
+    }
+
+    /// Counts its fixed updates.
+    struct Stepper(Rc<Cell<u32>>);
+
+    impl Scene for Stepper {
+        fn update(&mut self, _renderer: &mut Renderer, _time: &Time) -> Result<SceneCommand, SceneError> {
+            Ok(SceneCommand::None)
+        }
+
+        fn fixed_update(&mut self, _time: &Time) -> Result<(), SceneError> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+
+        fn event(&mut self, _event: &Event) -> Result<SceneCommand, SceneError> {
+            Ok(SceneCommand::None)
+        }
+
+        fn attach(&mut self, _app_info: &AppInfo) -> Result<(), SceneError> {
+            Ok(())
+        }
+
+        fn detach(&mut self) -> Result<(), SceneError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replay_uses_recorded_frame_times() {
+        // 0 + 5 + 30 + 5 ms, at 1/60 s per frame it would be 50 ms.
+        let recording = Recording::from_text(
+            "0 0 frame 0\n1 5000 frame 5000\n2 35000 frame 30000\n3 40000 frame 5000\n4 40000 end\n",
+        )
+        .unwrap();
+        let steps = Rc::new(Cell::new(0));
+        let config = HeadlessConfig {
+            timestep: Timestep::Fixed {
+                step: Duration::from_millis(10),
+                max_steps: 5,
+            },
+            ..Default::default()
+        }
+        .with_recording(&recording);
+
+        run_headless(Box::new(Stepper(steps.clone())), config).unwrap();
+
+        assert_eq!(steps.get(), 4);
+    }
+
+    #[test]
+    fn test_ctrl_c_key_interrupts() {
+        // What the terminal sends for Ctrl-C with the kitty keyboard protocol.
+        let ctrl_c = Parser::new().feed(b"\x1b[99;5u", Instant::now());
+        assert_eq!(ctrl_c.len(), 1);
+        let config = |keyboard_enhancement| HeadlessConfig {
+            frames: 5,
+            events: vec![(1, ctrl_c[0].clone())],
+            capture_frames: true,
+            keyboard_enhancement,
+            ..Default::default()
+        };
+
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config(true)).unwrap();
+        assert_eq!(out.exit, Some(ExitStatus::Interrupted));
+        assert_eq!(out.frames.len(), 1);
+
+        // Without the protocol Ctrl-C raises SIGINT, so a matching key event is an ordinary key.
+        let out = run_headless(Box::new(DotScene { x: 0, attached: false }), config(false)).unwrap();
+        assert_eq!(out.exit, None);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use crate::{
+    event::{Action, Event, Mouse},
+    key::{KeyCode, KeyEventKind, Modifiers},
+};
+
+/// The keyboard and mouse state, derived from the events of the current and earlier frames.
+///
+/// Characters are tracked case insensitively, `Char('A')` and `Char('a')` are the same key. Most
+/// terminals don't report key releases, then a key only counts as down in the frames it was
+/// pressed or repeated in (see
+/// [`AppStartupConfig::keyboard_enhancement`](crate::app::AppStartupConfig::keyboard_enhancement)).
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    /// Whether the terminal reports key releases.
+    key_releases: bool,
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+    modifiers: Modifiers,
+    buttons_down: HashSet<Mouse>,
+    buttons_pressed: HashSet<Mouse>,
+    buttons_released: HashSet<Mouse>,
+    mouse: Option<(i64, i64)>,
+    scroll: (i64, i64),
+    focused: bool,
+}
+
+fn normalize(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_lowercase().next().unwrap_or(c)),
+        code => code,
+    }
+}
+
+impl InputState {
+    pub(crate) fn new(key_releases: bool) -> InputState {
+        InputState {
+            key_releases,
+            focused: true,
+            ..Default::default()
+        }
+    }
+
+    /// Forgets what happened in the last frame, called before the events of a new frame.
+    pub(crate) fn begin_frame(&mut self) {
+        if !self.key_releases {
+            self.keys_down.clear();
+        }
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.scroll = (0, 0);
+    }
+
+    pub(crate) fn handle(&mut self, event: &Event) {
+        match event {
+            Event::Key(k) => {
+                let code = normalize(k.code);
+                self.modifiers = k.modifiers;
+
+                match k.kind {
+                    KeyEventKind::Press => {
+                        if self.keys_down.insert(code) || !self.key_releases {
+                            self.keys_pressed.insert(code);
+                        }
+                    }
+                    KeyEventKind::Repeat => {
+                        self.keys_down.insert(code);
+                    }
+                    KeyEventKind::Release => {
+                        self.key_releases = true;
+                        self.keys_down.remove(&code);
+                        self.keys_released.insert(code);
+                    }
+                }
+            }
+            Event::MouseButton(button, action, x, y) => {
+                self.mouse = Some((*x, *y));
+                match action {
+                    Action::Press => {
+                        self.buttons_down.insert(*button);
+                        self.buttons_pressed.insert(*button);
+                    }
+                    Action::Release => {
+                        self.buttons_down.remove(button);
+                        self.buttons_released.insert(*button);
+                    }
+                }
+            }
+            Event::MouseMove(x, y) => self.mouse = Some((*x, *y)),
+            Event::Scroll(dx, dy) => {
+                self.scroll.0 += dx;
+                self.scroll.1 += dy;
+            }
+            // Releases that happen while unfocused are never reported.
+            Event::FocusLost => {
+                self.focused = false;
+                self.keys_down.clear();
+                self.buttons_down.clear();
+                self.modifiers = Modifiers::NONE;
+            }
+            Event::FocusGained => self.focused = true,
+            Event::Paste(_) | Event::Action(_, _) | Event::Resize(_, _) => (),
+        }
+    }
+
+    /// Whether the key is held down.
+    pub fn is_key_down(&self, code: KeyCode) -> bool {
+        self.keys_down.contains(&normalize(code))
+    }
+
+    /// Whether the key went down this frame, repeats don't count.
+    pub fn is_key_pressed(&self, code: KeyCode) -> bool {
+        self.keys_pressed.contains(&normalize(code))
+    }
+
+    /// Whether the key was released this frame, never true if the terminal doesn't report
+    /// releases.
+    pub fn is_key_released(&self, code: KeyCode) -> bool {
+        self.keys_released.contains(&normalize(code))
+    }
+
+    /// The modifiers of the last key event.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn is_button_down(&self, button: Mouse) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: Mouse) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_button_released(&self, button: Mouse) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    /// Last known mouse position in pixels, `None` before the first mouse event.
+    pub fn mouse_position(&self) -> Option<(i64, i64)> {
+        self.mouse
+    }
+
+    /// Wheel ticks of this frame, like [`Event::Scroll`].
+    pub fn scroll(&self) -> (i64, i64) {
+        self.scroll
+    }
+
+    /// Whether the terminal has focus, assumed until it reports otherwise.
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event::{Action, Event, Mouse},
+        input::InputState,
+        key::{KeyCode, KeyEvent, KeyEventKind, Modifiers},
+    };
+
+    fn key(c: char, kind: KeyEventKind) -> Event {
+        Event::Key(KeyEvent::with_kind(KeyCode::Char(c), Modifiers::NONE, kind))
+    }
+
+    #[test]
+    fn test_key_and_mouse_state() {
+        let mut input = InputState::new(true);
+
+        input.begin_frame();
+        input.handle(&key('w', KeyEventKind::Press));
+        input.handle(&Event::MouseButton(Mouse::Left, Action::Press, 4, 6));
+        assert!(input.is_key_down(KeyCode::Char('W')));
+        assert!(input.is_key_pressed(KeyCode::Char('w')));
+        assert!(input.is_button_pressed(Mouse::Left));
+        assert_eq!(input.mouse_position(), Some((4, 6)));
+
+        input.begin_frame();
+        input.handle(&key('w', KeyEventKind::Repeat));
+        input.handle(&Event::MouseMove(5, 6));
+        assert!(input.is_key_down(KeyCode::Char('w')));
+        assert!(!input.is_key_pressed(KeyCode::Char('w')));
+        assert!(input.is_button_down(Mouse::Left));
+        assert!(!input.is_button_pressed(Mouse::Left));
+        assert_eq!(input.mouse_position(), Some((5, 6)));
+
+        input.begin_frame();
+        input.handle(&key('w', KeyEventKind::Release));
+        assert!(!input.is_key_down(KeyCode::Char('w')));
+        assert!(input.is_key_released(KeyCode::Char('w')));
+
+        input.handle(&Event::FocusLost);
+        assert!(!input.is_button_down(Mouse::Left));
+    }
+
+    #[test]
+    fn test_keys_without_releases() {
+        let mut input = InputState::new(false);
+
+        input.begin_frame();
+        input.handle(&key('a', KeyEventKind::Press));
+        assert!(input.is_key_down(KeyCode::Char('a')));
+
+        input.begin_frame();
+        assert!(!input.is_key_down(KeyCode::Char('a')));
+
+        // Auto repeat sends presses again.
+        input.handle(&key('a', KeyEventKind::Press));
+        assert!(input.is_key_pressed(KeyCode::Char('a')));
+    }
+}
@@ -0,0 +1,111 @@
+use std::ops::{BitOr, BitOrAssign, Sub};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    /// A printable character, with Ctrl held this is the lowercase letter.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// Function keys F1 to F12.
+    F(u8),
+}
+
+/// Set of modifier keys held while a key was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+    pub const SUPER: Modifiers = Modifiers(8);
+
+    /// Decodes the modifier parameter of xterm style escape sequences, which is one more than the
+    /// bit mask.
+    pub(crate) fn from_param(param: u32) -> Modifiers {
+        Modifiers((param.saturating_sub(1) & 0x0f) as u8)
+    }
+
+    pub fn contains(&self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, rhs: Modifiers) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The modifiers of `self` that are not in `rhs`.
+impl Sub for Modifiers {
+    type Output = Modifiers;
+
+    fn sub(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 & !rhs.0)
+    }
+}
+
+/// Terminals only report presses, unless the keyboard enhancement is enabled (see
+/// [`AppStartupConfig::keyboard_enhancement`](crate::app::AppStartupConfig::keyboard_enhancement)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum KeyEventKind {
+    #[default]
+    Press,
+    /// The key is held down.
+    Repeat,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+    pub kind: KeyEventKind,
+}
+
+impl KeyEvent {
+    /// A key press.
+    pub fn new(code: KeyCode, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent::with_kind(code, modifiers, KeyEventKind::Press)
+    }
+
+    pub fn with_kind(code: KeyCode, modifiers: Modifiers, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent {
+            code,
+            modifiers,
+            kind,
+        }
+    }
+}
+
+impl From<KeyCode> for KeyEvent {
+    fn from(code: KeyCode) -> Self {
+        KeyEvent::new(code, Modifiers::NONE)
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{framebuffer::line_points, renderer::ResizePolicy, Color, Framebuffer};
+
+/// A named image that is kept between frames and drawn over the frame by the
+/// [`Renderer`](crate::Renderer), see [`Renderer::layer`](crate::Renderer::layer).
+///
+/// Pixels that were never drawn (or were cleared) are transparent. Layers with a higher `z` are
+/// drawn over layers with a lower one, layers with the same `z` in the order they were created.
+#[derive(Clone)]
+pub struct Layer {
+    name: String,
+    pixels: Vec<Option<Color>>,
+    width: usize,
+    height: usize,
+    z: i32,
+    visible: bool,
+    offset: (i64, i64),
+    opacity: f32,
+}
+
+impl Layer {
+    pub(crate) fn new(name: &str, width: usize, height: usize) -> Layer {
+        Layer {
+            name: name.to_string(),
+            pixels: vec![None; width * height],
+            width,
+            height,
+            z: 0,
+            visible: true,
+            offset: (0, 0),
+            opacity: 1.0,
+        }
+    }
+
+    /// Changes the size, `policy` decides what happens to the content. Rescaling picks the
+    /// nearest pixel so transparent pixels stay transparent.
+    pub(crate) fn resize(&mut self, width: usize, height: usize, policy: ResizePolicy) {
+        let mut pixels = vec![None; width * height];
+
+        for y in 0..height {
+            for x in 0..width {
+                let (ox, oy) = match policy {
+                    ResizePolicy::Clear => continue,
+                    ResizePolicy::PreserveTopLeft => (x, y),
+                    ResizePolicy::Rescale => (x * self.width / width, y * self.height / height),
+                };
+                if ox < self.width && oy < self.height {
+                    pixels[y * width + x] = self.pixels[oy * self.width + ox].clone();
+                }
+            }
+        }
+
+        self.pixels = pixels;
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z
+    }
+
+    pub fn set_z(&mut self, z: i32) -> &mut Layer {
+        self.z = z;
+        self
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) -> &mut Layer {
+        self.visible = visible;
+        self
+    }
+
+    pub fn offset(&self) -> (i64, i64) {
+        self.offset
+    }
+
+    /// Moves the whole layer by `(x, y)` pixels without redrawing it.
+    pub fn set_offset(&mut self, x: i64, y: i64) -> &mut Layer {
+        self.offset = (x, y);
+        self
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// How much the layer covers what is below it, from 0 (invisible) to 1 (opaque).
+    pub fn set_opacity(&mut self, opacity: f32) -> &mut Layer {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Makes every pixel transparent.
+    pub fn clear(&mut self) {
+        self.pixels.fill(None);
+    }
+
+    /// Sets every pixel to `color`.
+    pub fn fill(&mut self, color: Color) {
+        self.pixels.fill(Some(color));
+    }
+
+    /// The color at `(x, y)`, `None` if the pixel is transparent or out of bounds.
+    pub fn get_pixel(&self, x: i64, y: i64) -> Option<Color> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+
+        self.pixels[y as usize * self.width + x as usize].clone()
+    }
+
+    pub fn pixel(&mut self, x: i64, y: i64, color: Color) -> bool {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return false;
+        }
+
+        self.pixels[y as usize * self.width + x as usize] = Some(color);
+        true
+    }
+
+    /// Makes the pixel at `(x, y)` transparent again.
+    pub fn erase(&mut self, x: i64, y: i64) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.pixels[y as usize * self.width + x as usize] = None;
+        }
+    }
+
+    pub fn line(&mut self, x0: i64, y0: i64, x1: i64, y1: i64, color: Color) {
+        line_points(x0, y0, x1, y1, |x, y| {
+            self.pixel(x, y, color.clone());
+        });
+    }
+
+    pub fn draw_framebuffer(&mut self, x: i64, y: i64, fb: &Framebuffer) {
+        for j in 0..fb.height() as i64 {
+            for i in 0..fb.width() as i64 {
+                if let Ok(c) = fb.get_pixel(i, j) {
+                    self.pixel(x + i, y + j, c);
+                }
+            }
+        }
+    }
+
+    /// Draws the layer over `fb`.
+    pub(crate) fn composite(&self, fb: &mut Framebuffer) {
+        if !self.visible || self.opacity == 0.0 {
+            return;
+        }
+
+        let (dx, dy) = self.offset;
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let color = match &self.pixels[y as usize * self.width + x as usize] {
+                    Some(c) => c,
+                    None => continue,
+                };
+
+                if self.opacity >= 1.0 {
+                    fb.pixel(x + dx, y + dy, color.clone());
+                } else if let Ok(under) = fb.get_pixel(x + dx, y + dy) {
+                    fb.pixel(x + dx, y + dy, color.blend(&under, self.opacity));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::MemoryBackend, color::ColorDepth, framebuffer::RenderMode, Color, Renderer};
+
+    #[test]
+    fn test_layers_are_composited() {
+        let backend = MemoryBackend::new(4, 2);
+        let mut renderer = Renderer::with_backend(Box::new(backend), RenderMode::Full, ColorDepth::TrueColor).unwrap();
+
+        renderer.layer("hud").set_z(1).pixel(0, 0, Color::red());
+        renderer.layer("background").set_z(-1).fill(Color::blue());
+        renderer.layer("sprite").set_offset(1, 1).pixel(0, 0, Color::green());
+
+        let fb = renderer.composited();
+        assert_eq!(fb.get_pixel(0, 0).unwrap(), Color::red());
+        assert_eq!(fb.get_pixel(1, 1).unwrap(), Color::green());
+        assert_eq!(fb.get_pixel(3, 1).unwrap(), Color::blue());
+
+        // Layers survive presenting, the frame below them doesn't.
+        renderer.pixel(2, 0, Color::white());
+        renderer.render().unwrap();
+        assert_eq!(renderer.composited().get_pixel(2, 0).unwrap(), Color::blue());
+
+        renderer.layer("hud").set_opacity(0.5);
+        renderer.layer("sprite").set_visible(false);
+        let fb = renderer.composited();
+        assert_eq!(fb.get_pixel(0, 0).unwrap(), Color::rgb(128, 0, 128));
+        assert_eq!(fb.get_pixel(1, 1).unwrap(), Color::blue());
+
+        assert!(renderer.remove_layer("background").is_some());
+        assert_eq!(renderer.composited().get_pixel(3, 1).unwrap(), Color::black());
+    }
+}
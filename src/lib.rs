@@ -3,22 +3,29 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod backend;
 pub mod color;
 pub mod framebuffer;
 pub mod app;
 pub mod renderer;
 pub mod profiler;
+pub mod snapshot;
+pub mod term;
 pub mod event;
+pub mod headless;
+pub mod key;
+pub mod input;
+pub mod bindings;
+pub mod recording;
+pub mod time;
+pub mod layer;
+mod glyph;
+mod parser;
+mod scene;
 
 pub use color::Color;
 pub use app::run;
 pub use renderer::Renderer;
 pub use framebuffer::Framebuffer;
 
-extern "C" {
-    pub(crate) fn term_disable_stdio_buffer();
-    pub(crate) fn term_reenable_stdio_buffer();
-    pub(crate) fn term_read_char() -> u8;
-}
-
 
@@ -0,0 +1,504 @@
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event::{Action, Event, Mouse},
+    key::{KeyCode, KeyEvent, KeyEventKind, Modifiers},
+};
+
+/// How long an escape byte waits for the rest of a sequence before it counts as the Esc key.
+pub(crate) const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+
+const ESC: u8 = 0x1b;
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Answers of the terminal to queries, they are not passed on to the app.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Reply {
+    /// `CSI ? <flags> u`, the active flags of the kitty keyboard protocol.
+    KeyboardFlags(u32),
+    /// `CSI ? <mode> ; <state> $ y`, answer to a DECRQM query whether a DEC private mode is set.
+    /// The state is 0 if the mode is not recognized, 1 or 2 if it is set or reset and 3 or 4 if
+    /// it is permanently set or reset.
+    Mode(u32, u32),
+    /// `CSI ? <attributes> c`, the primary device attributes. Every terminal answers this query,
+    /// so its reply marks the end of the replies to the queries sent before it.
+    DeviceAttributes,
+}
+
+impl Reply {
+    /// Whether this is a DECRPM reply saying `mode` can be set: it is set, reset or permanently
+    /// set.
+    pub(crate) fn supports_mode(&self, mode: u32) -> bool {
+        matches!(self, Reply::Mode(m, 1..=3) if *m == mode)
+    }
+}
+
+enum Parsed {
+    /// An event and the number of bytes it used.
+    Event(Event, usize),
+    /// A reply and the number of bytes it used.
+    Reply(Reply, usize),
+    /// A sequence that isn't understood, the bytes are dropped.
+    Skip(usize),
+    /// The input ends in the middle of a sequence.
+    Incomplete,
+}
+
+/// Turns the bytes read from the terminal into events, sequences may be split over several reads.
+pub(crate) struct Parser {
+    pending: Vec<u8>,
+    /// When the incomplete sequence at the start of `pending` was first seen.
+    pending_since: Option<Instant>,
+    replies: Vec<Reply>,
+}
+
+fn key(code: KeyCode, modifiers: Modifiers, len: usize) -> Parsed {
+    Parsed::Event(Event::Key(KeyEvent::new(code, modifiers)), len)
+}
+
+/// Decodes a single (possibly multi-byte) character.
+fn parse_char(buf: &[u8]) -> Parsed {
+    let b = buf[0];
+
+    match b {
+        b'\r' | b'\n' => return key(KeyCode::Enter, Modifiers::NONE, 1),
+        b'\t' => return key(KeyCode::Tab, Modifiers::NONE, 1),
+        0x7f | 0x08 => return key(KeyCode::Backspace, Modifiers::NONE, 1),
+        ESC => return key(KeyCode::Esc, Modifiers::NONE, 1),
+        0x00 => return key(KeyCode::Char(' '), Modifiers::CTRL, 1),
+        0x01..=0x1a => return key(KeyCode::Char((b'a' + b - 1) as char), Modifiers::CTRL, 1),
+        0x1c..=0x1f => return key(KeyCode::Char((b'\\' + b - 0x1c) as char), Modifiers::CTRL, 1),
+        _ => (),
+    }
+
+    let len = match b {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return key(KeyCode::Char(char::REPLACEMENT_CHARACTER), Modifiers::NONE, 1),
+    };
+    if buf.len() < len {
+        return Parsed::Incomplete;
+    }
+
+    match std::str::from_utf8(&buf[..len]) {
+        Ok(s) => key(KeyCode::Char(s.chars().next().unwrap()), Modifiers::NONE, len),
+        Err(_) => key(KeyCode::Char(char::REPLACEMENT_CHARACTER), Modifiers::NONE, 1),
+    }
+}
+
+/// Keys of `CSI <n> ~` sequences.
+fn tilde_key(n: u32) -> Option<KeyCode> {
+    Some(match n {
+        1 | 7 => KeyCode::Home,
+        2 => KeyCode::Insert,
+        3 => KeyCode::Delete,
+        4 | 8 => KeyCode::End,
+        5 => KeyCode::PageUp,
+        6 => KeyCode::PageDown,
+        11..=15 => KeyCode::F((n - 10) as u8),
+        17..=21 => KeyCode::F((n - 11) as u8),
+        23 | 24 => KeyCode::F((n - 12) as u8),
+        _ => return None,
+    })
+}
+
+/// Keys identified by the final byte of CSI and SS3 sequences.
+fn final_key(b: u8) -> Option<KeyCode> {
+    Some(match b {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        _ => return None,
+    })
+}
+
+/// Decodes an SGR mouse report `ESC [ < <button> ; <column> ; <row> M`, with `m` instead of `M`
+/// for releases. Positions are 0-based cells.
+fn parse_mouse(params: &[u32], final_byte: u8, len: usize) -> Parsed {
+    let (b, x, y) = match params {
+        [b, x, y] => (*b, *x as i64 - 1, *y as i64 - 1),
+        _ => return Parsed::Skip(len),
+    };
+
+    let event = if b & 64 != 0 {
+        match b & 3 {
+            0 => Event::Scroll(0, 1),
+            1 => Event::Scroll(0, -1),
+            2 => Event::Scroll(-1, 0),
+            _ => Event::Scroll(1, 0),
+        }
+    } else if b & 32 != 0 {
+        Event::MouseMove(x, y)
+    } else {
+        let button = match b & 3 {
+            0 => Mouse::Left,
+            1 => Mouse::Middle,
+            2 => Mouse::Right,
+            _ => return Parsed::Skip(len),
+        };
+        let action = if final_byte == b'M' { Action::Press } else { Action::Release };
+        Event::MouseButton(button, action, x, y)
+    };
+
+    Parsed::Event(event, len)
+}
+
+/// Parses a bracketed paste, `buf` starts with [`PASTE_START`]. Terminals send line breaks as
+/// `\r`, they are turned into `\n`.
+fn parse_paste(buf: &[u8]) -> Parsed {
+    let text = &buf[PASTE_START.len()..];
+    let end = match text.windows(PASTE_END.len()).position(|w| w == PASTE_END) {
+        Some(end) => end,
+        None => return Parsed::Incomplete,
+    };
+
+    let text = String::from_utf8_lossy(&text[..end]).replace("\r\n", "\n").replace('\r', "\n");
+    Parsed::Event(Event::Paste(text), PASTE_START.len() + end + PASTE_END.len())
+}
+
+/// Keys of `CSI <code> u` sequences, `code` is a unicode code point or one of the private use
+/// codes of the kitty keyboard protocol. `shifted` is the code with shift applied, 0 if unknown.
+fn unicode_key(code: u32, shifted: u32, modifiers: Modifiers) -> Option<KeyCode> {
+    Some(match code {
+        9 => KeyCode::Tab,
+        13 | 57414 => KeyCode::Enter,
+        27 => KeyCode::Esc,
+        8 | 127 => KeyCode::Backspace,
+        // Keypad digits.
+        57399..=57408 => KeyCode::Char(char::from_digit(code - 57399, 10)?),
+        // The remaining keypad, media and modifier keys.
+        57344..=63743 => return None,
+        _ if shifted != 0 && modifiers.contains(Modifiers::SHIFT) => KeyCode::Char(char::from_u32(shifted)?),
+        _ => KeyCode::Char(char::from_u32(code)?),
+    })
+}
+
+/// Parses `ESC [ <params> <final>`, `buf` starts with the escape byte.
+fn parse_csi(buf: &[u8]) -> Parsed {
+    let mut end = 2;
+    loop {
+        match buf.get(end) {
+            None => return Parsed::Incomplete,
+            Some(0x20..=0x3f) => end += 1,
+            Some(0x40..=0x7e) => break,
+            // Not a valid sequence, only the escape byte is dropped.
+            Some(_) => return Parsed::Skip(1),
+        }
+    }
+
+    let len = end + 1;
+    let final_byte = buf[end];
+    // Private sequences start with one of `<=>?`.
+    let marker = match buf[2] {
+        m @ b'<'..=b'?' => Some(m),
+        _ => None,
+    };
+    let start = if marker.is_some() { 3 } else { 2 };
+    // Intermediate bytes (like the '$' of a DECRPM reply) come after the parameters.
+    let mut params_end = end;
+    while params_end > start && (0x20..=0x2f).contains(&buf[params_end - 1]) {
+        params_end -= 1;
+    }
+    let intermediates = &buf[params_end..end];
+
+    // Parameters are separated by ';' and can have sub-parameters separated by ':'.
+    let fields: Vec<Vec<u32>> = std::str::from_utf8(&buf[start..params_end])
+        .unwrap_or("")
+        .split(';')
+        .map(|p| p.split(':').map(|n| n.parse().unwrap_or(0)).collect())
+        .collect();
+    let sub = |i: usize, j: usize| fields.get(i).and_then(|f| f.get(j)).copied().unwrap_or(0);
+    let param = |i: usize| sub(i, 0);
+
+    match (marker, final_byte) {
+        (Some(b'<'), b'M' | b'm') => {
+            let params: Vec<u32> = (0..fields.len()).map(param).collect();
+            return parse_mouse(&params, final_byte, len);
+        }
+        (Some(b'?'), b'u') => return Parsed::Reply(Reply::KeyboardFlags(param(0)), len),
+        (Some(b'?'), b'c') => return Parsed::Reply(Reply::DeviceAttributes, len),
+        (Some(b'?'), b'y') if intermediates == b"$" => return Parsed::Reply(Reply::Mode(param(0), param(1)), len),
+        (Some(_), _) => return Parsed::Skip(len),
+        (None, _) => (),
+    }
+
+    let modifiers = Modifiers::from_param(param(1));
+    let kind = match sub(1, 1) {
+        2 => KeyEventKind::Repeat,
+        3 => KeyEventKind::Release,
+        _ => KeyEventKind::Press,
+    };
+
+    let code = match final_byte {
+        b'~' if param(0) == 200 => return parse_paste(buf),
+        b'~' => tilde_key(param(0)),
+        b'u' => unicode_key(param(0), sub(0, 1), modifiers),
+        b'I' => return Parsed::Event(Event::FocusGained, len),
+        b'O' => return Parsed::Event(Event::FocusLost, len),
+        b'Z' => return key(KeyCode::Tab, Modifiers::SHIFT, len),
+        b => final_key(b),
+    };
+
+    match code {
+        Some(code) => Parsed::Event(Event::Key(KeyEvent::with_kind(code, modifiers, kind)), len),
+        None => Parsed::Skip(len),
+    }
+}
+
+fn parse(buf: &[u8]) -> Parsed {
+    if buf[0] != ESC {
+        return parse_char(buf);
+    }
+
+    match buf.get(1) {
+        None => Parsed::Incomplete,
+        Some(b'[') => parse_csi(buf),
+        Some(b'O') => match buf.get(2) {
+            None => Parsed::Incomplete,
+            Some(b) => match final_key(*b) {
+                Some(code) => key(code, Modifiers::NONE, 3),
+                None => Parsed::Skip(3),
+            },
+        },
+        Some(&ESC) => key(KeyCode::Esc, Modifiers::NONE, 1),
+        // Alt sends the key prefixed with an escape byte.
+        Some(_) => match parse_char(&buf[1..]) {
+            Parsed::Event(Event::Key(mut k), len) => {
+                k.modifiers |= Modifiers::ALT;
+                Parsed::Event(Event::Key(k), len + 1)
+            }
+            p => p,
+        },
+    }
+}
+
+/// Parses the start of an incomplete sequence when no more input is expected.
+fn parse_timed_out(buf: &[u8]) -> Parsed {
+    // The end of a paste always arrives eventually, no matter how long it takes.
+    if buf.starts_with(PASTE_START) {
+        return Parsed::Incomplete;
+    }
+
+    if buf[0] == ESC {
+        if let Some(b'[' | b'O') = buf.get(1) {
+            return key(KeyCode::Char(buf[1] as char), Modifiers::ALT, 2);
+        }
+        return key(KeyCode::Esc, Modifiers::NONE, 1);
+    }
+
+    // An incomplete character.
+    key(KeyCode::Char(char::REPLACEMENT_CHARACTER), Modifiers::NONE, 1)
+}
+
+impl Parser {
+    pub(crate) fn new() -> Parser {
+        Parser {
+            pending: vec![],
+            pending_since: None,
+            replies: vec![],
+        }
+    }
+
+    /// Removes and returns the replies parsed so far.
+    pub(crate) fn take_replies(&mut self) -> Vec<Reply> {
+        mem::take(&mut self.replies)
+    }
+
+    /// Whether an incomplete sequence is waiting for more input or its timeout.
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Parses `input` together with what was left over from earlier calls. Should be called
+    /// regularly even without new input, so a lone Esc is reported after its timeout.
+    pub(crate) fn feed(&mut self, input: &[u8], now: Instant) -> Vec<Event> {
+        if !input.is_empty() {
+            self.pending.extend_from_slice(input);
+            self.pending_since = None;
+        }
+
+        let mut events = vec![];
+        let mut i = 0;
+
+        while i < self.pending.len() {
+            match parse(&self.pending[i..]) {
+                Parsed::Event(e, len) => {
+                    events.push(e);
+                    i += len;
+                }
+                Parsed::Reply(r, len) => {
+                    self.replies.push(r);
+                    i += len;
+                }
+                Parsed::Skip(len) => i += len,
+                Parsed::Incomplete => {
+                    let since = *self.pending_since.get_or_insert(now);
+                    if now.duration_since(since) < ESC_TIMEOUT {
+                        break;
+                    }
+
+                    match parse_timed_out(&self.pending[i..]) {
+                        Parsed::Event(e, len) => {
+                            events.push(e);
+                            i += len;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+
+        self.pending.drain(..i);
+        if self.pending.is_empty() {
+            self.pending_since = None;
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        event::{Action, Event, Mouse},
+        key::{KeyCode, KeyEvent, KeyEventKind, Modifiers},
+        parser::{Parser, Reply},
+    };
+
+    fn keys(events: Vec<Event>) -> Vec<(KeyCode, Modifiers)> {
+        events
+            .into_iter()
+            .map(|e| match e {
+                Event::Key(KeyEvent { code, modifiers, .. }) => (code, modifiers),
+                e => panic!("not a key: {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        let input = "a\x1b[A\x1b[1;5C\x1bOP\x1b[15~\x1b[3;3~\x01\x1bxé\r\x7f\x1b[Z";
+        assert_eq!(
+            keys(parser.feed(input.as_bytes(), now)),
+            vec![
+                (KeyCode::Char('a'), Modifiers::NONE),
+                (KeyCode::Up, Modifiers::NONE),
+                (KeyCode::Right, Modifiers::CTRL),
+                (KeyCode::F(1), Modifiers::NONE),
+                (KeyCode::F(5), Modifiers::NONE),
+                (KeyCode::Delete, Modifiers::ALT),
+                (KeyCode::Char('a'), Modifiers::CTRL),
+                (KeyCode::Char('x'), Modifiers::ALT),
+                (KeyCode::Char('é'), Modifiers::NONE),
+                (KeyCode::Enter, Modifiers::NONE),
+                (KeyCode::Backspace, Modifiers::NONE),
+                (KeyCode::Tab, Modifiers::SHIFT),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_sequence_and_lone_escape() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        assert!(parser.feed(b"\x1b[1;", now).is_empty());
+        assert_eq!(keys(parser.feed(b"2B", now)), vec![(KeyCode::Down, Modifiers::SHIFT)]);
+
+        assert!(parser.feed(b"\x1b", now).is_empty());
+        assert!(parser.feed(b"", now + Duration::from_millis(10)).is_empty());
+        assert_eq!(
+            keys(parser.feed(b"", now + Duration::from_millis(100))),
+            vec![(KeyCode::Esc, Modifiers::NONE)]
+        );
+    }
+
+    #[test]
+    fn test_parse_mouse() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        let input = b"\x1b[<0;3;5M\x1b[<32;4;5M\x1b[<0;4;5m\x1b[<2;1;1M\x1b[<65;10;10M\x1b[<35;7;2Mq";
+        assert_eq!(
+            parser.feed(input, now),
+            vec![
+                Event::MouseButton(Mouse::Left, Action::Press, 2, 4),
+                Event::MouseMove(3, 4),
+                Event::MouseButton(Mouse::Left, Action::Release, 3, 4),
+                Event::MouseButton(Mouse::Right, Action::Press, 0, 0),
+                Event::Scroll(0, -1),
+                Event::MouseMove(6, 1),
+                Event::Key(KeyCode::Char('q').into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paste_and_focus() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        assert_eq!(parser.feed(b"\x1b[I\x1b[200~wasd\r", now), vec![Event::FocusGained]);
+        assert!(parser.feed(b"", now + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            parser.feed("\x1b[Aé\x1b[201~\x1b[O".as_bytes(), now + Duration::from_secs(1)),
+            vec![Event::Paste("wasd\n\x1b[Aé".to_string()), Event::FocusLost]
+        );
+    }
+
+    #[test]
+    fn test_kitty_keyboard() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        let input = b"\x1b[?15u\x1b[?62;22c\x1b[97u\x1b[97;1:2u\x1b[97;1:3u\x1b[97:65;2u\x1b[13;5u\x1b[1;1:3D\x1b[57441;2u";
+        assert_eq!(
+            parser.feed(input, now),
+            vec![
+                Event::Key(KeyEvent::new(KeyCode::Char('a'), Modifiers::NONE)),
+                Event::Key(KeyEvent::with_kind(KeyCode::Char('a'), Modifiers::NONE, KeyEventKind::Repeat)),
+                Event::Key(KeyEvent::with_kind(KeyCode::Char('a'), Modifiers::NONE, KeyEventKind::Release)),
+                Event::Key(KeyEvent::new(KeyCode::Char('A'), Modifiers::SHIFT)),
+                Event::Key(KeyEvent::new(KeyCode::Enter, Modifiers::CTRL)),
+                Event::Key(KeyEvent::with_kind(KeyCode::Left, Modifiers::NONE, KeyEventKind::Release)),
+            ]
+        );
+        assert_eq!(parser.take_replies(), vec![Reply::KeyboardFlags(15), Reply::DeviceAttributes]);
+        assert!(parser.take_replies().is_empty());
+    }
+
+    #[test]
+    fn test_mode_reply() {
+        let mut parser = Parser::new();
+        let now = Instant::now();
+
+        assert!(parser.feed(b"\x1b[?2026;2$y\x1b[?62;22c", now).is_empty());
+        assert_eq!(parser.take_replies(), vec![Reply::Mode(2026, 2), Reply::DeviceAttributes]);
+
+        assert!(parser.feed(b"\x1b[?2026;3$y\x1b[?2026;0$y\x1b[?2026;4$y", now).is_empty());
+        let replies = parser.take_replies();
+        assert_eq!(replies, vec![Reply::Mode(2026, 3), Reply::Mode(2026, 0), Reply::Mode(2026, 4)]);
+        let supported: Vec<bool> = replies.iter().map(|r| r.supports_mode(2026)).collect();
+        assert_eq!(supported, vec![true, false, false]);
+        assert!(!Reply::Mode(2004, 1).supports_mode(2026));
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event::{Action, Event, Mouse},
+    framebuffer::RenderMode,
+    key::{KeyCode, KeyEvent, KeyEventKind, Modifiers},
+};
+
+/// An event and when it was delivered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    /// Index of the frame the event was handed to the scene in.
+    pub frame: usize,
+    /// Time since the recording started.
+    pub time: Duration,
+    pub event: Event,
+}
+
+/// The events of a run of an app, see
+/// [`AppStartupConfig::record`](crate::app::AppStartupConfig::record).
+///
+/// The file starts with the screen the app started with, followed by one line per frame with the
+/// frame time in microseconds and one line per event, all prefixed by their frame and the time in
+/// microseconds. The last line holds the number of frames:
+///
+/// ```text
+/// 0 0 start 80 24 halfblock
+/// 0 12 frame 0
+/// 1 16871 frame 16859
+/// 1 16875 key c:119 0 press
+/// 2 35004 frame 18129
+/// 2 35010 end
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    /// The screen at the start of the run, `None` for recordings made without one.
+    pub header: Option<RecordingHeader>,
+    /// Number of frames of the recorded run.
+    pub frames: usize,
+    /// How long each frame took, the first one is 0. Can be shorter than `frames` (or empty for
+    /// recordings made without frame times), the missing frames use the frame time of the replay.
+    pub frame_times: Vec<Duration>,
+    pub events: Vec<RecordedEvent>,
+}
+
+/// The terminal size and render mode an app was started with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordingHeader {
+    /// Width of the terminal in cells.
+    pub width: usize,
+    /// Height of the terminal in cells.
+    pub height: usize,
+    pub render_mode: RenderMode,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    IoError(io::Error),
+    ParseError(String),
+}
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("esc", KeyCode::Esc),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("insert", KeyCode::Insert),
+    ("delete", KeyCode::Delete),
+];
+
+const BUTTONS: &[(&str, Mouse)] = &[("left", Mouse::Left), ("middle", Mouse::Middle), ("right", Mouse::Right)];
+
+const KINDS: &[(&str, KeyEventKind)] = &[
+    ("press", KeyEventKind::Press),
+    ("repeat", KeyEventKind::Repeat),
+    ("release", KeyEventKind::Release),
+];
+
+const RENDER_MODES: &[(&str, RenderMode)] = &[
+    ("full", RenderMode::Full),
+    ("halfblock", RenderMode::HalfBlock),
+    ("braille", RenderMode::Braille),
+    ("quadrant", RenderMode::Quadrant),
+    ("sextant", RenderMode::Sextant),
+];
+
+fn name<T: PartialEq>(table: &[(&'static str, T)], value: &T) -> &'static str {
+    table.iter().find(|(_, v)| v == value).map(|(n, _)| *n).unwrap()
+}
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Press => "press",
+        Action::Release => "release",
+    }
+}
+
+fn parse_action(s: &str) -> Option<Action> {
+    match s {
+        "press" => Some(Action::Press),
+        "release" => Some(Action::Release),
+        _ => None,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+fn parse_hex(s: &str) -> Option<String> {
+    let bytes: Option<Vec<u8>> = (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect();
+    String::from_utf8(bytes?).ok()
+}
+
+fn format_header(header: &RecordingHeader) -> String {
+    format!(
+        "start {} {} {}",
+        header.width,
+        header.height,
+        name(RENDER_MODES, &header.render_mode)
+    )
+}
+
+fn format_event(event: &Event) -> String {
+    match event {
+        Event::Key(k) => {
+            let code = match k.code {
+                KeyCode::Char(c) => format!("c:{}", c as u32),
+                KeyCode::F(n) => format!("f:{}", n),
+                code => name(NAMED_KEYS, &code).to_string(),
+            };
+            let mut modifiers = 0;
+            for (i, m) in [Modifiers::SHIFT, Modifiers::ALT, Modifiers::CTRL, Modifiers::SUPER].iter().enumerate() {
+                if k.modifiers.contains(*m) {
+                    modifiers |= 1 << i;
+                }
+            }
+            format!("key {} {} {}", code, modifiers, name(KINDS, &k.kind))
+        }
+        Event::MouseButton(b, a, x, y) => format!("mouse {} {} {} {}", name(BUTTONS, b), action_name(*a), x, y),
+        Event::MouseMove(x, y) => format!("move {} {}", x, y),
+        Event::Scroll(x, y) => format!("scroll {} {}", x, y),
+        Event::Paste(text) => format!("paste {}", hex(text.as_bytes())),
+        Event::FocusGained => "focus_gained".to_string(),
+        Event::FocusLost => "focus_lost".to_string(),
+        Event::Action(a, action) => format!("action {} {}", action_name(*action), hex(a.as_bytes())),
+        Event::Resize(w, h) => format!("resize {} {}", w, h),
+    }
+}
+
+fn parse_event(words: &[&str]) -> Option<Event> {
+    let int = |i: usize| words.get(i).and_then(|w| w.parse::<i64>().ok());
+
+    Some(match words {
+        ["key", code, modifiers, kind] => {
+            let code = match code.split_once(':') {
+                Some(("c", n)) => KeyCode::Char(char::from_u32(n.parse().ok()?)?),
+                Some(("f", n)) => KeyCode::F(n.parse().ok()?),
+                _ => lookup(NAMED_KEYS, code)?,
+            };
+            let bits: u8 = modifiers.parse().ok()?;
+            let mut mods = Modifiers::NONE;
+            for (i, m) in [Modifiers::SHIFT, Modifiers::ALT, Modifiers::CTRL, Modifiers::SUPER].iter().enumerate() {
+                if bits & (1 << i) != 0 {
+                    mods |= *m;
+                }
+            }
+            Event::Key(KeyEvent::with_kind(code, mods, lookup(KINDS, kind)?))
+        }
+        ["mouse", b, a, _, _] => Event::MouseButton(lookup(BUTTONS, b)?, parse_action(a)?, int(3)?, int(4)?),
+        ["move", _, _] => Event::MouseMove(int(1)?, int(2)?),
+        ["scroll", _, _] => Event::Scroll(int(1)?, int(2)?),
+        ["paste", text] => Event::Paste(parse_hex(text)?),
+        ["paste"] => Event::Paste(String::new()),
+        ["focus_gained"] => Event::FocusGained,
+        ["focus_lost"] => Event::FocusLost,
+        ["action", a, name] => Event::Action(parse_hex(name)?, parse_action(a)?),
+        ["resize", _, _] => Event::Resize(int(1)?, int(2)?),
+        _ => return None,
+    })
+}
+
+impl Recording {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut last = Duration::ZERO;
+        let mut elapsed = Duration::ZERO;
+        let mut events = self.events.iter().peekable();
+
+        if let Some(header) = &self.header {
+            let _ = writeln!(text, "0 0 {}", format_header(header));
+        }
+
+        let mut write_event = |text: &mut String, e: &RecordedEvent| {
+            let _ = writeln!(text, "{} {} {}", e.frame, e.time.as_micros(), format_event(&e.event));
+            last = e.time;
+        };
+
+        for (frame, delta) in self.frame_times.iter().enumerate() {
+            while let Some(e) = events.next_if(|e| e.frame < frame) {
+                write_event(&mut text, e);
+            }
+            elapsed += *delta;
+            let _ = writeln!(text, "{} {} frame {}", frame, elapsed.as_micros(), delta.as_micros());
+        }
+        for e in events {
+            write_event(&mut text, e);
+        }
+        let _ = writeln!(text, "{} {} end", self.frames, last.max(elapsed).as_micros());
+
+        text
+    }
+
+    /// Parses the format written by [`Recording::to_text`], a recording that was cut short (e.g.
+    /// by a crash) has no end line, then the frame after the last event is the last one.
+    pub fn from_text(text: &str) -> Result<Recording, RecordingError> {
+        let mut recording = Recording::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let err = || RecordingError::ParseError(format!("line {}: invalid event \"{}\"", i + 1, line));
+            let words: Vec<&str> = line.split(' ').collect();
+
+            let (frame, time) = match (words.first().map(|w| w.parse()), words.get(1).map(|w| w.parse())) {
+                (Some(Ok(frame)), Some(Ok(time))) => (frame, Duration::from_micros(time)),
+                _ => return Err(err()),
+            };
+
+            match &words[2..] {
+                ["start", width, height, mode] => {
+                    recording.header = Some(RecordingHeader {
+                        width: width.parse().map_err(|_| err())?,
+                        height: height.parse().map_err(|_| err())?,
+                        render_mode: lookup(RENDER_MODES, mode).ok_or_else(err)?,
+                    });
+                    continue;
+                }
+                ["end"] => {
+                    recording.frames = frame;
+                    return Ok(recording);
+                }
+                ["frame", delta] => {
+                    let delta = Duration::from_micros(delta.parse().map_err(|_| err())?);
+                    recording.frame_times.resize(frame, Duration::ZERO);
+                    recording.frame_times.push(delta);
+                }
+                words => {
+                    let event = parse_event(words).ok_or_else(err)?;
+                    recording.events.push(RecordedEvent { frame, time, event });
+                }
+            }
+            recording.frames = recording.frames.max(frame + 1);
+        }
+
+        Ok(recording)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, RecordingError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Recording::from_text(&text),
+            Err(e) => Err(RecordingError::IoError(e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RecordingError> {
+        match fs::write(path, self.to_text()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(RecordingError::IoError(e)),
+        }
+    }
+
+    /// The events of each frame, the result has [`Recording::frames`] entries.
+    pub fn frame_events(&self) -> Vec<Vec<Event>> {
+        let mut frames: Vec<Vec<Event>> = (0..self.frames).map(|_| vec![]).collect();
+        for e in &self.events {
+            if let Some(f) = frames.get_mut(e.frame) {
+                f.push(e.event.clone());
+            }
+        }
+        frames
+    }
+}
+
+/// Writes the screen, frame times and events of a running app to a file, every frame with events
+/// is flushed so the recording survives a crash.
+pub(crate) struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+    frames: usize,
+}
+
+impl Recorder {
+    pub(crate) fn create<P: AsRef<Path>>(path: P, header: RecordingHeader) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "0 0 {}", format_header(&header))?;
+        Ok(Recorder {
+            out,
+            start: Instant::now(),
+            frames: 0,
+        })
+    }
+
+    /// Records how long the next frame took and its events.
+    pub(crate) fn record(&mut self, delta: Duration, events: &[Event]) -> io::Result<()> {
+        let time = self.start.elapsed().as_micros();
+        writeln!(self.out, "{} {} frame {}", self.frames, time, delta.as_micros())?;
+        for e in events {
+            writeln!(self.out, "{} {} {}", self.frames, time, format_event(e))?;
+        }
+        if !events.is_empty() {
+            self.out.flush()?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the end line.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        writeln!(self.out, "{} {} end", self.frames, self.start.elapsed().as_micros())?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        event::{Action, Event, Mouse},
+        framebuffer::RenderMode,
+        key::{KeyCode, KeyEvent, KeyEventKind, Modifiers},
+        recording::{RecordedEvent, Recording, RecordingHeader},
+    };
+
+    #[test]
+    fn test_recording_round_trip() {
+        let events = vec![
+            Event::Key(KeyEvent::with_kind(KeyCode::Char('é'), Modifiers::CTRL | Modifiers::ALT, KeyEventKind::Release)),
+            Event::Key(KeyEvent::new(KeyCode::F(5), Modifiers::SHIFT)),
+            Event::Key(KeyCode::PageDown.into()),
+            Event::MouseButton(Mouse::Right, Action::Press, 3, -1),
+            Event::MouseMove(7, 8),
+            Event::Scroll(0, -1),
+            Event::Paste("a b\nc".to_string()),
+            Event::FocusLost,
+            Event::Action("move up".to_string(), Action::Release),
+            Event::Resize(80, 48),
+        ];
+        let recording = Recording {
+            header: Some(RecordingHeader {
+                width: 120,
+                height: 40,
+                render_mode: RenderMode::Sextant,
+            }),
+            frames: 12,
+            frame_times: (0..11).map(|i| Duration::from_micros(i * 1000 % 7000)).collect(),
+            events: events
+                .into_iter()
+                .enumerate()
+                .map(|(i, event)| RecordedEvent {
+                    frame: i,
+                    time: Duration::from_micros(i as u64 * 1000),
+                    event,
+                })
+                .collect(),
+        };
+
+        let text = recording.to_text();
+        assert_eq!(Recording::from_text(&text).unwrap(), recording);
+
+        // Cut short without an end line, after the first event of frame 1.
+        let cut: Vec<&str> = text.lines().take(5).collect();
+        assert_eq!(Recording::from_text(&cut.join("\n")).unwrap().frames, 2);
+    }
+}
@@ -1,63 +1,344 @@
-use std::io::{stdout, Stdout};
+use std::{
+    io::{self, Write},
+    mem,
+};
 
 use crate::{
-    framebuffer::{Framebuffer, FramebufferError}, profile, Color
+    backend::{Backend, TerminalBackend},
+    color::ColorDepth,
+    framebuffer::{Framebuffer, FramebufferError, RenderMode},
+    layer::Layer,
+    profile, Color,
 };
 
 #[derive(Debug)]
 pub enum RendererError {
     FBError(FramebufferError),
+    IoError(io::Error),
+}
+
+/// Which mouse events the terminal reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseMode {
+    #[default]
+    Disabled,
+    /// Presses, releases and the scroll wheel.
+    Button,
+    /// Like `Button`, and movement while a button is held.
+    Drag,
+    /// Like `Button`, and all movement.
+    AnyMotion,
+}
+
+/// Begin and end of a synchronized update, the terminal shows everything in between at once.
+const SYNC_BEGIN: &[u8] = b"\x1b[?2026h";
+const SYNC_END: &[u8] = b"\x1b[?2026l";
+
+/// What happens to the frame and the layers when the screen size changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizePolicy {
+    /// Start over with an empty frame.
+    #[default]
+    Clear,
+    /// Keep what still fits, anchored at the top left corner.
+    PreserveTopLeft,
+    /// Scale the old content to the new size.
+    Rescale,
+}
+
+/// How the renderer treats a frame once it was presented, see
+/// [`AppStartupConfig::renderer_options`](crate::app::AppStartupConfig::renderer_options).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RendererOptions {
+    /// The color a new or cleared frame starts with.
+    pub clear_color: Color,
+    /// Keep the frame after presenting it instead of clearing it, so scenes only have to draw
+    /// what changed.
+    pub retained: bool,
+    /// Without `retained` this only matters for the layers, the frame is empty after every
+    /// present anyway.
+    pub resize_policy: ResizePolicy,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        RendererOptions {
+            clear_color: Color::black(),
+            retained: false,
+            resize_policy: ResizePolicy::Clear,
+        }
+    }
+}
+
+/// Turns bracketed paste and focus reporting on or off.
+fn input_reporting(out: &mut Box<dyn Backend>, enable: bool) -> Result<(), RendererError> {
+    match if enable {
+        out.write_all(b"\x1b[?2004h\x1b[?1004h")
+    } else {
+        out.write_all(b"\x1b[?2004l\x1b[?1004l")
+    } {
+        Ok(_) => Ok(()),
+        Err(e) => Err(RendererError::IoError(e)),
+    }
+}
+
+/// Draws the visible layers over `fb`, lowest z first.
+fn composite(layers: &[Layer], fb: &mut Framebuffer) {
+    profile!();
+    let mut layers: Vec<&Layer> = layers.iter().collect();
+    layers.sort_by_key(|l| l.z());
+    for layer in layers {
+        layer.composite(fb);
+    }
+}
+
+impl MouseMode {
+    /// The DEC private mode that enables reporting, `None` for `Disabled`.
+    fn private_mode(self) -> Option<u16> {
+        match self {
+            MouseMode::Disabled => None,
+            MouseMode::Button => Some(1000),
+            MouseMode::Drag => Some(1002),
+            MouseMode::AnyMotion => Some(1003),
+        }
+    }
 }
 
 pub struct Renderer {
-    out: Stdout,
+    out: Box<dyn Backend>,
+    mode: RenderMode,
+    depth: ColorDepth,
     fb: Framebuffer,
+    /// The frame that is currently on screen, `None` if the next frame has to be fully redrawn.
+    presented: Option<Framebuffer>,
+    /// Drawn over `fb` when it is presented, in creation order (sorted by z when compositing).
+    layers: Vec<Layer>,
+    options: RendererOptions,
+    /// Wrap every frame in begin and end synchronized update sequences (DEC mode 2026), so the
+    /// terminal never shows a half drawn frame.
+    synchronized: bool,
+    /// A frame is written into this first and sent to the output with a single write.
+    buffer: Vec<u8>,
+    mouse_mode: MouseMode,
 }
 
 impl Renderer {
-    pub(crate) fn new() -> Result<Renderer, RendererError> {
+    pub(crate) fn new(mode: RenderMode, depth: ColorDepth) -> Result<Renderer, RendererError> {
+        Renderer::with_backend(Box::new(TerminalBackend::new()), mode, depth)
+    }
+
+    /// Creates a renderer that presents its frames through `out`, the screen size is taken from
+    /// the backend.
+    pub fn with_backend(
+        mut out: Box<dyn Backend>,
+        mode: RenderMode,
+        depth: ColorDepth,
+    ) -> Result<Renderer, RendererError> {
         profile!();
-        let fb = match Framebuffer::new_terminal_size(Color::grey(0)) {
-            Ok(fb) => fb,
-            Err(e) => return Err(RendererError::FBError(e)),
+        let fb = match out.size() {
+            Some((w, h)) => {
+                let (w, h) = mode.pixel_size(w, h);
+                Framebuffer::new(w, h, Color::grey(0))
+            }
+            None => return Err(RendererError::FBError(FramebufferError::CantGetTerminalSize)),
         };
 
-        let mut out = stdout();
-
+        match fb.alternate_screen(&mut out, true) {
+            Ok(_) => (),
+            Err(e) => return Err(RendererError::FBError(e)),
+        }
         match fb.hide_cursor(&mut out, true) {
             Ok(_) => (),
             Err(e) => return Err(RendererError::FBError(e)),
         }
+        input_reporting(&mut out, true)?;
 
-        Ok(Renderer { out, fb })
+        Ok(Renderer {
+            out,
+            mode,
+            depth,
+            fb,
+            presented: None,
+            layers: vec![],
+            options: RendererOptions::default(),
+            synchronized: false,
+            buffer: vec![],
+            mouse_mode: MouseMode::Disabled,
+        })
     }
 
-    pub(crate) fn render(&mut self) -> Result<(), RendererError> {
+    /// Presents the current frame with the layers drawn over it. Unless the renderer is retained
+    /// the next frame starts out cleared, the layers are always kept.
+    pub fn render(&mut self) -> Result<(), RendererError> {
         profile!();
-        match self.fb.render(&mut self.out) {
-            Ok(_) => (),
-            Err(e) => return Err(RendererError::FBError(e)),
+        // The layers are drawn over a copy, so a retained frame doesn't keep them.
+        let composited = match self.layers.is_empty() {
+            true => None,
+            false => Some(self.composited()),
         };
-        match self.fb.reset_cursor(&mut self.out) {
+        let frame = composited.as_ref().unwrap_or(&self.fb);
+
+        self.buffer.clear();
+        if self.synchronized {
+            self.buffer.extend_from_slice(SYNC_BEGIN);
+        }
+        let start = self.buffer.len();
+        match frame.render(&mut self.buffer, self.mode, self.depth, self.presented.as_ref()) {
             Ok(_) => (),
             Err(e) => return Err(RendererError::FBError(e)),
         };
-        self.fb.clear(Color::grey(0));
+        // If nothing changed, nothing is written.
+        if self.buffer.len() > start {
+            if self.synchronized {
+                self.buffer.extend_from_slice(SYNC_END);
+            }
+            match self.out.write_all(&self.buffer).and_then(|_| self.out.flush()) {
+                Ok(_) => (),
+                Err(e) => return Err(RendererError::IoError(e)),
+            }
+        }
+
+        // What is on screen now, the next frame is diffed against it.
+        let presented = match composited {
+            Some(fb) => fb,
+            None if self.options.retained => self.fb.clone(),
+            None => {
+                let next = match self.presented.take() {
+                    Some(fb) => fb,
+                    None => Framebuffer::new(self.fb.width(), self.fb.height(), Color::black()),
+                };
+                mem::replace(&mut self.fb, next)
+            }
+        };
+        self.presented = Some(presented);
+
+        if !self.options.retained {
+            self.fb.clear(self.options.clear_color.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Resizes the screen to `w` x `h` pixels, the frame and the layers are resized according
+    /// to the resize policy.
+    pub fn resize(&mut self, w: i64, h: i64) {
+        let (w, h) = (w as usize, h as usize);
+        let clear_color = self.options.clear_color.clone();
+
+        self.fb = match self.options.resize_policy {
+            ResizePolicy::Rescale if self.fb.width() > 0 && self.fb.height() > 0 => {
+                Framebuffer::new_resized(&self.fb, w, h)
+            }
+            ResizePolicy::PreserveTopLeft => {
+                let mut fb = Framebuffer::new(w, h, clear_color);
+                fb.draw_framebuffer(0, 0, &self.fb);
+                fb
+            }
+            _ => Framebuffer::new(w, h, clear_color),
+        };
+        self.presented = None;
+        for layer in &mut self.layers {
+            layer.resize(w, h, self.options.resize_policy);
+        }
+    }
+
+    /// Changes the options, the current frame is cleared to the new clear color.
+    pub fn set_options(&mut self, options: RendererOptions) {
+        self.fb.clear(options.clear_color.clone());
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &RendererOptions {
+        &self.options
+    }
+
+    /// Wraps every frame in synchronized update sequences, only enable this if the terminal
+    /// supports DEC mode 2026. The app detects it on startup.
+    pub fn set_synchronized_output(&mut self, synchronized: bool) {
+        self.synchronized = synchronized;
+    }
+
+    pub fn synchronized_output(&self) -> bool {
+        self.synchronized
+    }
+
+    /// The layer called `name`, it is created empty and screen sized if it doesn't exist yet.
+    pub fn layer(&mut self, name: &str) -> &mut Layer {
+        let i = match self.layers.iter().position(|l| l.name() == name) {
+            Some(i) => i,
+            None => {
+                let (w, h) = (self.fb.width(), self.fb.height());
+                self.layers.push(Layer::new(name, w, h));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[i]
+    }
+
+    pub fn get_layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.name() == name)
+    }
+
+    pub fn remove_layer(&mut self, name: &str) -> Option<Layer> {
+        let i = self.layers.iter().position(|l| l.name() == name)?;
+        Some(self.layers.remove(i))
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// The current frame with the layers drawn over it, what [`Renderer::render`] would present.
+    pub fn composited(&self) -> Framebuffer {
+        let mut fb = self.fb.clone();
+        composite(&self.layers, &mut fb);
+        fb
+    }
+
+    /// Changes which mouse events the terminal reports, they are delivered as SGR (1006) reports.
+    pub fn set_mouse_mode(&mut self, mouse_mode: MouseMode) -> Result<(), RendererError> {
+        let mut seq = String::new();
+        if let Some(mode) = self.mouse_mode.private_mode() {
+            seq.push_str(&format!("\x1b[?{}l\x1b[?1006l", mode));
+        }
+        if let Some(mode) = mouse_mode.private_mode() {
+            seq.push_str(&format!("\x1b[?{}h\x1b[?1006h", mode));
+        }
+
+        match self.out.write_all(seq.as_bytes()).and_then(|_| self.out.flush()) {
+            Ok(_) => (),
+            Err(e) => return Err(RendererError::IoError(e)),
+        }
+        self.mouse_mode = mouse_mode;
 
         Ok(())
     }
 
-    pub(crate) fn resize(&mut self, w: i64, h: i64) {
-        self.fb = Framebuffer::new(w as usize, h as usize, Color::black());
+    pub fn mouse_mode(&self) -> MouseMode {
+        self.mouse_mode
     }
 
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn color_depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    /// Size of the screen in pixels, depending on the render mode one cell holds multiple pixels.
     pub fn screen_size(&self) -> (i64, i64) {
         (self.fb.width() as i64, self.fb.height() as i64)
     }
 
+    /// The frame that is currently being drawn, without the layers.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.fb
+    }
+
     pub fn pixel(&mut self, x: i64, y: i64, color: Color) -> bool {
         self.fb.pixel(x, y, color)
-     }
+    }
 
     pub fn line(&mut self, x0: i64, y0: i64, x1: i64, y1: i64, color: Color) {
         self.fb.line(x0, y0, x1, y1, color);
@@ -70,6 +351,98 @@ impl Renderer {
 
 impl Drop for Renderer {
     fn drop(&mut self) {
-        self.fb.hide_cursor(&mut self.out, false).unwrap();
+        // Errors are ignored, there is nothing left to do if the terminal is gone and panicking
+        // while unwinding would abort.
+        let _ = Color::reset(&mut self.out);
+        let _ = self.set_mouse_mode(MouseMode::Disabled);
+        let _ = input_reporting(&mut self.out, false);
+        let _ = self.fb.hide_cursor(&mut self.out, false);
+        let _ = self.fb.alternate_screen(&mut self.out, false);
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::MemoryBackend,
+        color::ColorDepth,
+        framebuffer::RenderMode,
+        renderer::{RendererOptions, ResizePolicy},
+        Color, Renderer,
+    };
+
+    fn renderer(options: RendererOptions) -> Renderer {
+        let backend = MemoryBackend::new(4, 2);
+        let mut renderer = Renderer::with_backend(Box::new(backend), RenderMode::Full, ColorDepth::TrueColor).unwrap();
+        renderer.set_options(options);
+        renderer
+    }
+
+    #[test]
+    fn test_clear_and_retained_frames() {
+        let mut r = renderer(RendererOptions {
+            clear_color: Color::blue(),
+            ..Default::default()
+        });
+        r.pixel(1, 1, Color::red());
+        r.render().unwrap();
+        assert_eq!(r.framebuffer().get_pixel(1, 1).unwrap(), Color::blue());
+
+        let mut r = renderer(RendererOptions {
+            retained: true,
+            resize_policy: ResizePolicy::PreserveTopLeft,
+            ..Default::default()
+        });
+        r.layer("hud").pixel(0, 0, Color::green());
+        r.pixel(1, 1, Color::red());
+        r.render().unwrap();
+        r.render().unwrap();
+        assert_eq!(r.framebuffer().get_pixel(1, 1).unwrap(), Color::red());
+        // The layer is not baked into the retained frame.
+        assert_eq!(r.framebuffer().get_pixel(0, 0).unwrap(), Color::black());
+
+        r.resize(2, 3);
+        assert_eq!(r.screen_size(), (2, 3));
+        assert_eq!(r.framebuffer().get_pixel(1, 1).unwrap(), Color::red());
+        assert_eq!(r.get_layer("hud").unwrap().get_pixel(0, 0), Some(Color::green()));
+    }
+
+    #[test]
+    fn test_synchronized_output() {
+        let backend = MemoryBackend::new(4, 2);
+        let mut r = Renderer::with_backend(Box::new(backend.clone()), RenderMode::Full, ColorDepth::TrueColor).unwrap();
+        r.set_synchronized_output(true);
+        r.render().unwrap();
+        backend.take();
+
+        // Nothing changed, nothing is written.
+        r.render().unwrap();
+        assert!(backend.take().is_empty());
+
+        r.pixel(1, 0, Color::red());
+        r.render().unwrap();
+        let out = backend.take();
+        assert!(out.starts_with(b"\x1b[?2026h\x1b[1;2H"));
+        assert!(out.ends_with(b"\x1b[0m\x1b[?2026l"));
+    }
+
+    #[test]
+    fn test_resize_rescales() {
+        let mut r = renderer(RendererOptions {
+            retained: true,
+            resize_policy: ResizePolicy::Rescale,
+            ..Default::default()
+        });
+        for x in 0..4 {
+            r.pixel(x, 0, Color::white());
+        }
+        r.layer("hud").pixel(3, 1, Color::red());
+
+        r.resize(8, 4);
+        assert_eq!(r.framebuffer().get_pixel(7, 1).unwrap(), Color::white());
+        assert_eq!(r.framebuffer().get_pixel(7, 3).unwrap(), Color::black());
+        assert_eq!(r.get_layer("hud").unwrap().get_pixel(7, 3), Some(Color::red()));
+        assert_eq!(r.get_layer("hud").unwrap().get_pixel(5, 3), None);
     }
 }
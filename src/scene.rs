@@ -0,0 +1,89 @@
+use crate::app::{AppInfo, Scene, SceneCommand, SceneError};
+
+/// The scenes of an app, only the top one gets events. Scenes below overlays are drawn as well.
+pub(crate) struct SceneStack {
+    scenes: Vec<Box<dyn Scene>>,
+    /// Counts pushes, pops and replaces, so callers can tell whether the top scene changed.
+    changes: u64,
+}
+
+impl SceneStack {
+    pub(crate) fn new(scene: Box<dyn Scene>) -> SceneStack {
+        SceneStack {
+            scenes: vec![scene],
+            changes: 0,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub(crate) fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub(crate) fn top(&mut self) -> Option<&mut Box<dyn Scene>> {
+        self.scenes.last_mut()
+    }
+
+    /// The scenes that are drawn this frame, bottom first: the top scene and everything below it
+    /// that is covered only by overlays.
+    pub(crate) fn visible(&mut self) -> &mut [Box<dyn Scene>] {
+        let mut start = self.scenes.len();
+        while start > 0 {
+            start -= 1;
+            if !self.scenes[start].is_overlay() {
+                break;
+            }
+        }
+        &mut self.scenes[start..]
+    }
+
+    /// Attaches the initial scene.
+    pub(crate) fn attach(&mut self, app_info: &AppInfo) -> Result<(), SceneError> {
+        match self.top() {
+            Some(scene) => scene.attach(app_info),
+            None => Ok(()),
+        }
+    }
+
+    /// Detaches and removes all scenes, top first. All scenes are detached even if one fails, the
+    /// first error is returned.
+    pub(crate) fn detach(&mut self) -> Result<(), SceneError> {
+        let mut result = Ok(());
+        while let Some(mut scene) = self.scenes.pop() {
+            let detached = scene.detach();
+            if result.is_ok() {
+                result = detached;
+            }
+        }
+        result
+    }
+
+    /// Carries out `command`, returns the exit code if it asks to quit.
+    pub(crate) fn apply(&mut self, command: SceneCommand, app_info: &AppInfo) -> Result<Option<i32>, SceneError> {
+        match command {
+            SceneCommand::None => return Ok(None),
+            SceneCommand::Push(mut scene) => {
+                scene.attach(app_info)?;
+                self.scenes.push(scene);
+            }
+            SceneCommand::Pop => {
+                if let Some(mut scene) = self.scenes.pop() {
+                    scene.detach()?;
+                }
+            }
+            SceneCommand::Replace(mut scene) => {
+                if let Some(mut old) = self.scenes.pop() {
+                    old.detach()?;
+                }
+                scene.attach(app_info)?;
+                self.scenes.push(scene);
+            }
+            SceneCommand::Quit(code) => return Ok(Some(code)),
+        }
+        self.changes += 1;
+        Ok(None)
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use crate::{framebuffer::FramebufferError, profile, Color, Framebuffer};
+
+/// Set this environment variable to create or overwrite golden files instead of comparing against
+/// them.
+pub const UPDATE_ENV: &str = "TERM_GFX_UPDATE_SNAPSHOTS";
+
+/// Printable ASCII characters used as palette keys in the text format.
+const KEYS: std::ops::RangeInclusive<u8> = b'!'..=b'~';
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    IoError(io::Error),
+    ImageError(image::ImageError),
+    /// The text format can only hold as many colors as there are palette keys.
+    TooManyColors(usize),
+    ParseError(String),
+    /// The golden file has a different size, (width, height) of the golden file and the frame.
+    SizeMismatch((usize, usize), (usize, usize)),
+    /// This many pixels differ by more than the tolerance, a diff image was written to the path.
+    Mismatch(usize, PathBuf),
+    /// The golden file doesn't exist and [`UPDATE_ENV`] is not set.
+    MissingGolden(PathBuf),
+}
+
+impl Framebuffer {
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), FramebufferError> {
+        profile!();
+        let img: RgbImage = ImageBuffer::from_fn(self.width() as u32, self.height() as u32, |x, y| {
+            let c = self.get_pixel(x as i64, y as i64).unwrap();
+            Rgb([c.red, c.green, c.blue])
+        });
+
+        match img.save(path) {
+            Ok(_) => Ok(()),
+            Err(image::ImageError::IoError(e)) => Err(FramebufferError::IoError(e)),
+            Err(e) => Err(FramebufferError::ImageError(e)),
+        }
+    }
+
+    /// Serializes the framebuffer to a small text format, a palette with one key per color followed
+    /// by the pixels as keys:
+    ///
+    /// ```text
+    /// 3 2
+    /// ! 000000
+    /// " ff0000
+    ///
+    /// !"!
+    /// !!"
+    /// ```
+    pub fn to_text(&self) -> Result<String, SnapshotError> {
+        let mut palette: Vec<Color> = vec![];
+        let mut keys: HashMap<(u8, u8, u8), char> = HashMap::new();
+        let mut rows = String::new();
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let c = self.get_pixel(x as i64, y as i64).unwrap();
+                let next = palette.len();
+                let key = *keys.entry((c.red, c.green, c.blue)).or_insert_with(|| {
+                    palette.push(c.clone());
+                    KEYS.clone().nth(next).map(char::from).unwrap_or('\0')
+                });
+                rows.push(key);
+            }
+            rows.push('\n');
+        }
+
+        if palette.len() > KEYS.len() {
+            return Err(SnapshotError::TooManyColors(palette.len()));
+        }
+
+        let mut text = format!("{} {}\n", self.width(), self.height());
+        for (key, c) in KEYS.zip(&palette) {
+            text.push_str(&format!("{} {:02x}{:02x}{:02x}\n", key as char, c.red, c.green, c.blue));
+        }
+        text.push('\n');
+        text.push_str(&rows);
+
+        Ok(text)
+    }
+
+    /// Parses the format written by [`Framebuffer::to_text`].
+    pub fn from_text(text: &str) -> Result<Framebuffer, SnapshotError> {
+        let err = |msg: &str| SnapshotError::ParseError(msg.to_string());
+        let mut lines = text.lines();
+
+        let size: Vec<usize> = match lines.next() {
+            Some(l) => l.split(' ').filter_map(|n| n.parse().ok()).collect(),
+            None => return Err(err("empty file")),
+        };
+        let (width, height) = match size[..] {
+            [w, h] => (w, h),
+            _ => return Err(err("expected the size as \"<width> <height>\"")),
+        };
+
+        let mut palette: HashMap<char, Color> = HashMap::new();
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+
+            let mut chars = line.chars();
+            let (key, hex) = match (chars.next(), chars.next(), chars.as_str()) {
+                (Some(k), Some(' '), hex) if hex.len() == 6 && hex.is_ascii() => (k, hex),
+                _ => return Err(err(&format!("invalid palette entry \"{}\"", line))),
+            };
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            match (channel(0), channel(2), channel(4)) {
+                (Ok(r), Ok(g), Ok(b)) => palette.insert(key, Color::rgb(r, g, b)),
+                _ => return Err(err(&format!("invalid color \"{}\"", hex))),
+            };
+        }
+
+        let mut fb = Framebuffer::new(width, height, Color::black());
+        for y in 0..height {
+            let line = match lines.next() {
+                Some(l) => l,
+                None => return Err(err("missing rows")),
+            };
+            if line.chars().count() != width {
+                return Err(err(&format!("row {} has the wrong length", y)));
+            }
+
+            for (x, key) in line.chars().enumerate() {
+                match palette.get(&key) {
+                    Some(c) => fb.pixel(x as i64, y as i64, c.clone()),
+                    None => return Err(err(&format!("unknown palette key '{}'", key))),
+                };
+            }
+        }
+
+        Ok(fb)
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "png")
+}
+
+/// Maps an error of [`Framebuffer::save_png`], which only fails to encode or write the file.
+fn png_error(e: FramebufferError) -> SnapshotError {
+    match e {
+        FramebufferError::ImageError(e) => SnapshotError::ImageError(e),
+        FramebufferError::IoError(e) => SnapshotError::IoError(e),
+        e => SnapshotError::IoError(io::Error::other(format!("{:?}", e))),
+    }
+}
+
+fn write_golden(fb: &Framebuffer, path: &Path) -> Result<(), SnapshotError> {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(SnapshotError::IoError(e));
+        }
+    }
+
+    if is_png(path) {
+        fb.save_png(path).map_err(png_error)
+    } else {
+        match fs::write(path, fb.to_text()?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SnapshotError::IoError(e)),
+        }
+    }
+}
+
+fn read_golden(path: &Path) -> Result<Framebuffer, SnapshotError> {
+    if is_png(path) {
+        match image::open(path) {
+            Ok(img) => {
+                let img = img.to_rgb8();
+                let mut fb = Framebuffer::new(img.width() as usize, img.height() as usize, Color::black());
+                for (x, y, p) in img.enumerate_pixels() {
+                    fb.pixel(x as i64, y as i64, Color::rgb(p.0[0], p.0[1], p.0[2]));
+                }
+                Ok(fb)
+            }
+            Err(e) => Err(SnapshotError::ImageError(e)),
+        }
+    } else {
+        match fs::read_to_string(path) {
+            Ok(text) => Framebuffer::from_text(&text),
+            Err(e) => Err(SnapshotError::IoError(e)),
+        }
+    }
+}
+
+/// Path of the diff image written next to the golden file `path`.
+pub fn diff_path(path: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".diff.png");
+    path.with_file_name(name)
+}
+
+/// Compares `fb` against the golden file at `path`, which is a PNG if it ends in `.png` and the
+/// text format of [`Framebuffer::to_text`] otherwise.
+///
+/// Pixels match if no channel differs by more than `tolerance`. If [`UPDATE_ENV`] is set, `fb` is
+/// written as the new golden file instead, a missing golden file is only created that way. On a
+/// mismatch an image with the differing pixels in red is written to [`diff_path`].
+pub fn compare(fb: &Framebuffer, path: &Path, tolerance: u8) -> Result<(), SnapshotError> {
+    profile!();
+    if env::var_os(UPDATE_ENV).is_some() {
+        return write_golden(fb, path);
+    }
+    if !path.exists() {
+        return Err(SnapshotError::MissingGolden(path.to_path_buf()));
+    }
+
+    let golden = read_golden(path)?;
+    let (w, h) = (fb.width(), fb.height());
+    if (golden.width(), golden.height()) != (w, h) {
+        return Err(SnapshotError::SizeMismatch((golden.width(), golden.height()), (w, h)));
+    }
+
+    let mut diff = Framebuffer::new(w, h, Color::black());
+    let mut mismatches = 0;
+
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            let a = fb.get_pixel(x, y).unwrap();
+            let b = golden.get_pixel(x, y).unwrap();
+
+            let within = a.red.abs_diff(b.red) <= tolerance
+                && a.green.abs_diff(b.green) <= tolerance
+                && a.blue.abs_diff(b.blue) <= tolerance;
+
+            if within {
+                diff.pixel(x, y, Color::grey(((a.red as u32 + a.green as u32 + a.blue as u32) / 12) as u8));
+            } else {
+                diff.pixel(x, y, Color::red());
+                mismatches += 1;
+            }
+        }
+    }
+
+    if mismatches == 0 {
+        return Ok(());
+    }
+
+    let diff_path = diff_path(path);
+    match diff.save_png(&diff_path) {
+        Ok(_) => Err(SnapshotError::Mismatch(mismatches, diff_path)),
+        Err(e) => Err(png_error(e)),
+    }
+}
+
+/// Like [`compare`], but panics with a readable message on a mismatch, for use in tests.
+pub fn assert_snapshot<P: AsRef<Path>>(fb: &Framebuffer, path: P, tolerance: u8) {
+    let path = path.as_ref();
+    match compare(fb, path, tolerance) {
+        Ok(_) => (),
+        Err(SnapshotError::Mismatch(n, diff)) => panic!(
+            "{} pixels differ from snapshot {}, see {} (set {} to update)",
+            n,
+            path.display(),
+            diff.display(),
+            UPDATE_ENV
+        ),
+        Err(SnapshotError::MissingGolden(_)) => panic!(
+            "missing golden file {}, rerun with {}=1 to create it",
+            path.display(),
+            UPDATE_ENV
+        ),
+        Err(e) => panic!("snapshot {} failed: {:?}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        snapshot::{assert_snapshot, compare, diff_path, write_golden, SnapshotError},
+        Color, Framebuffer,
+    };
+
+    #[test]
+    fn test_line_snapshot() {
+        let mut fb = Framebuffer::new(16, 8, Color::black());
+        fb.line(0, 0, 15, 7, Color::red());
+        fb.line(15, 0, 2, 6, Color::green());
+        fb.line(3, 7, 3, 1, Color::blue());
+
+        assert_snapshot(&fb, concat!(env!("CARGO_MANIFEST_DIR"), "/res/snapshots/line.txt"), 0);
+    }
+
+    #[test]
+    fn test_resized_snapshot() {
+        let img = Framebuffer::new_image(concat!(env!("CARGO_MANIFEST_DIR"), "/res/test_image.png")).unwrap();
+        let fb = Framebuffer::new_resized(&img, 7, 5);
+
+        assert_snapshot(&fb, concat!(env!("CARGO_MANIFEST_DIR"), "/res/snapshots/resized.png"), 1);
+    }
+
+    #[test]
+    fn test_snapshot_mismatch() {
+        let dir = env::temp_dir().join(format!("term_gfx_snapshot_{}", std::process::id()));
+        let path = dir.join("dot.txt");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut fb = Framebuffer::new(4, 4, Color::grey(100));
+        // A missing golden file fails instead of being created.
+        match compare(&fb, &path, 0) {
+            Err(SnapshotError::MissingGolden(p)) => assert_eq!(p, path),
+            r => panic!("expected a missing golden file, got {:?}", r),
+        }
+        assert!(!path.exists());
+
+        write_golden(&fb, &path).unwrap();
+        compare(&fb, &path, 0).unwrap();
+
+        fb.pixel(1, 2, Color::grey(103));
+        compare(&fb, &path, 3).unwrap();
+
+        match compare(&fb, &path, 2) {
+            Err(SnapshotError::Mismatch(1, diff)) => assert_eq!(diff, diff_path(&path)),
+            r => panic!("expected a mismatch, got {:?}", r),
+        }
+        assert!(diff_path(&path).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let dir = env::temp_dir().join(format!("term_gfx_snapshot_errors_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let fb = Framebuffer::new(2, 2, Color::red());
+
+        // Golden files that can't be written because a directory is in the way.
+        for name in ["frame.png", "frame.txt"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            match write_golden(&fb, &dir.join(name)) {
+                Err(SnapshotError::IoError(_)) => (),
+                r => panic!("expected an IO error for {}, got {:?}", name, r),
+            }
+        }
+
+        // A multi-byte character in a palette color is an error, not a panic.
+        match Framebuffer::from_text("1 1\n! 0\u{e9}000\n\n!\n") {
+            Err(SnapshotError::ParseError(_)) => (),
+            r => panic!("expected a parse error, got {:?}", r.map(|_| ())),
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
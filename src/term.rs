@@ -0,0 +1,358 @@
+use std::{
+    io,
+    mem::{self, MaybeUninit},
+    os::fd::RawFd,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// The state of the terminal before a [`Terminal`] changed its mode, so it can be restored from a
+/// panic hook.
+static SAVED: Mutex<Option<(RawFd, libc::termios)>> = Mutex::new(None);
+
+/// Set by the SIGWINCH handler, a burst of signals sets it only once.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+/// Write end of the pipe of the current [`Wakeup`], -1 if there is none.
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// A terminal device whose mode can be changed and restored.
+///
+/// The original settings are saved when the `Terminal` is created and restored exactly when it is
+/// dropped or [`Terminal::restore`] is called.
+pub struct Terminal {
+    fd: RawFd,
+    original: libc::termios,
+    /// Whether `fd` was opened by us and has to be closed.
+    owned: bool,
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn get_attr(fd: RawFd) -> io::Result<libc::termios> {
+    let mut attr = MaybeUninit::<libc::termios>::uninit();
+    check(unsafe { libc::tcgetattr(fd, attr.as_mut_ptr()) })?;
+    Ok(unsafe { attr.assume_init() })
+}
+
+fn set_attr(fd: RawFd, attr: &libc::termios) -> io::Result<()> {
+    check(unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, attr) })?;
+    Ok(())
+}
+
+/// Restores whatever terminal state was saved last, used when the app can't unwind normally.
+pub(crate) fn restore_saved() {
+    // `try_lock` because this might run in a panic hook while the lock is held.
+    if let Ok(mut saved) = SAVED.try_lock() {
+        if let Some((fd, attr)) = saved.take() {
+            let _ = set_attr(fd, &attr);
+        }
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    Ok(())
+}
+
+/// Wakes up a thread blocked in [`wait_readable`] on the [`Wakeup`] fd. Only does async signal
+/// safe things, so it can be called from signal handlers.
+pub(crate) fn wake() {
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // If the pipe is full there is already a wakeup pending.
+        unsafe { libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+    wake();
+}
+
+/// A self-pipe that becomes readable on SIGWINCH or [`wake`], so waiting for input can be
+/// interrupted. Only one should exist at a time.
+pub(crate) struct Wakeup {
+    read_fd: RawFd,
+    write_fd: RawFd,
+    previous: libc::sigaction,
+}
+
+impl Wakeup {
+    /// Creates the pipe and installs the SIGWINCH handler, the previous handler is restored on
+    /// drop.
+    pub(crate) fn new() -> io::Result<Wakeup> {
+        let mut fds = [0; 2];
+        check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let [read_fd, write_fd] = fds;
+
+        let close = || unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        };
+        if let Err(e) = set_nonblocking(read_fd).and_then(|_| set_nonblocking(write_fd)) {
+            close();
+            return Err(e);
+        }
+
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = on_sigwinch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+
+        WAKE_FD.store(write_fd, Ordering::SeqCst);
+        let mut previous = MaybeUninit::<libc::sigaction>::uninit();
+        if let Err(e) = check(unsafe { libc::sigaction(libc::SIGWINCH, &action, previous.as_mut_ptr()) }) {
+            WAKE_FD.store(-1, Ordering::SeqCst);
+            close();
+            return Err(e);
+        }
+
+        Ok(Wakeup {
+            read_fd,
+            write_fd,
+            previous: unsafe { previous.assume_init() },
+        })
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// Empties the pipe and returns whether the terminal was resized since the last call, no
+    /// matter how many SIGWINCH arrived in between.
+    pub(crate) fn take_resized(&self) -> bool {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+
+        RESIZED.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        unsafe { libc::sigaction(libc::SIGWINCH, &self.previous, std::ptr::null_mut()) };
+        let _ = WAKE_FD.compare_exchange(self.write_fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// Blocks until one of `fds` is readable or the timeout (`None` for no timeout) passed. Returns
+/// early if interrupted by a signal.
+pub(crate) fn wait_readable(fds: &[RawFd], timeout: Option<Duration>) -> io::Result<()> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout = match timeout {
+        Some(t) => t.as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+
+    match check(unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) }) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+impl Terminal {
+    /// Uses `fd`, which has to refer to a terminal, it is not closed on drop.
+    pub fn from_fd(fd: RawFd) -> io::Result<Terminal> {
+        let original = get_attr(fd)?;
+        Ok(Terminal {
+            fd,
+            original,
+            owned: false,
+        })
+    }
+
+    pub fn stdin() -> io::Result<Terminal> {
+        Terminal::from_fd(libc::STDIN_FILENO)
+    }
+
+    /// Opens the controlling terminal `/dev/tty`, works even if stdin is redirected.
+    pub fn open_tty() -> io::Result<Terminal> {
+        let fd = check(unsafe { libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) })?;
+
+        match get_attr(fd) {
+            Ok(original) => Ok(Terminal {
+                fd,
+                original,
+                owned: true,
+            }),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    fn set_mode(&self, attr: &libc::termios) -> io::Result<()> {
+        *SAVED.lock().unwrap() = Some((self.fd, self.original));
+        set_attr(self.fd, attr)
+    }
+
+    /// Disables line buffering and echo, reads return immediately even without input. Signals
+    /// like Ctrl-C keep working.
+    pub fn enable_cbreak(&self) -> io::Result<()> {
+        let mut attr = self.original;
+        attr.c_lflag &= !(libc::ICANON | libc::ECHO);
+        attr.c_cc[libc::VMIN] = 0;
+        attr.c_cc[libc::VTIME] = 0;
+        self.set_mode(&attr)
+    }
+
+    /// Like [`Terminal::enable_cbreak`], but additionally disables signal keys, flow control and
+    /// all input and output processing, so every byte is passed through unchanged.
+    pub fn enable_raw(&self) -> io::Result<()> {
+        let mut attr = self.original;
+        unsafe { libc::cfmakeraw(&mut attr) };
+        attr.c_cc[libc::VMIN] = 0;
+        attr.c_cc[libc::VTIME] = 0;
+        self.set_mode(&attr)
+    }
+
+    /// Restores the settings the terminal had when this `Terminal` was created.
+    pub fn restore(&self) -> io::Result<()> {
+        let mut saved = SAVED.lock().unwrap();
+        if saved.is_some_and(|(fd, _)| fd == self.fd) {
+            *saved = None;
+        }
+        set_attr(self.fd, &self.original)
+    }
+
+    /// Reads as much input as fits into `buf`, returns 0 if no input is available.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => Ok(0),
+                _ => Err(err),
+            };
+        }
+
+        Ok(n as usize)
+    }
+
+    /// Reads a single byte, `None` if no input is available.
+    pub fn read_byte(&self) -> io::Result<Option<u8>> {
+        let mut buf = [0u8; 1];
+        Ok(match self.read(&mut buf)? {
+            0 => None,
+            _ => Some(buf[0]),
+        })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.restore();
+        if self.owned {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, time::Duration};
+
+    use crate::term::{get_attr, wait_readable, Terminal, Wakeup};
+
+    /// Opens a pseudo terminal, returns the master and the slave fd.
+    fn open_pty() -> (libc::c_int, libc::c_int) {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let name = CStr::from_ptr(libc::ptsname(master));
+            let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            (master, slave)
+        }
+    }
+
+    #[test]
+    fn test_raw_mode_and_read_on_pty() {
+        let (master, slave) = open_pty();
+        let original = get_attr(slave).unwrap();
+
+        {
+            let terminal = Terminal::from_fd(slave).unwrap();
+            terminal.enable_raw().unwrap();
+            let raw = get_attr(slave).unwrap();
+            assert_eq!(raw.c_lflag & (libc::ICANON | libc::ECHO | libc::ISIG), 0);
+            assert_eq!(raw.c_cc[libc::VMIN], 0);
+
+            let mut buf = [0u8; 1024];
+            assert_eq!(terminal.read(&mut buf).unwrap(), 0);
+
+            // More than one buffer, written at once like a large paste.
+            let input: Vec<u8> = (0..3000).map(|i| b'a' + (i % 26) as u8).collect();
+            let written = unsafe { libc::write(master, input.as_ptr() as *const libc::c_void, input.len()) };
+            assert_eq!(written, input.len() as isize);
+
+            let mut read = vec![];
+            while read.len() < input.len() {
+                wait_readable(&[slave], Some(Duration::from_secs(5))).unwrap();
+                let n = terminal.read(&mut buf).unwrap();
+                assert!(n > 0);
+                read.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(read, input);
+            assert_eq!(terminal.read_byte().unwrap(), None);
+        }
+
+        // Dropping the terminal restores the original mode.
+        let restored = get_attr(slave).unwrap();
+        assert_eq!(restored.c_lflag, original.c_lflag);
+        assert_eq!(restored.c_iflag, original.c_iflag);
+        assert_eq!(restored.c_cc[libc::VMIN], original.c_cc[libc::VMIN]);
+
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn test_sigwinch_burst_is_coalesced() {
+        let wakeup = Wakeup::new().unwrap();
+        assert!(!wakeup.take_resized());
+
+        for _ in 0..3 {
+            unsafe { libc::raise(libc::SIGWINCH) };
+        }
+        // Returns right away because the pipe is readable.
+        wait_readable(&[wakeup.fd()], Some(Duration::from_secs(10))).unwrap();
+
+        assert!(wakeup.take_resized());
+        assert!(!wakeup.take_resized());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Timing information handed to [`Scene::update`](crate::app::Scene::update) and
+/// [`Scene::fixed_update`](crate::app::Scene::fixed_update).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Time {
+    /// Time since the last call, the step size for fixed updates.
+    pub delta: Duration,
+    /// Time since the app started, for fixed updates the simulated time.
+    pub elapsed: Duration,
+    /// Number of calls before this one.
+    pub frame: u64,
+    /// How far the simulation is between the last and the next fixed update, from 0 to 1. Can be
+    /// used to interpolate what is drawn, always 0 without a fixed timestep.
+    pub alpha: f64,
+}
+
+/// How [`Scene::fixed_update`](crate::app::Scene::fixed_update) is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timestep {
+    /// Only `update` is called, once per frame.
+    #[default]
+    Variable,
+    /// `fixed_update` is called every `step` of passed time, independent of the frame rate. If a
+    /// frame took very long it is called at most `max_steps` times and the simulation falls
+    /// behind instead of catching up. Starting an app with a zero `step` panics.
+    Fixed { step: Duration, max_steps: u32 },
+}
+
+impl Timestep {
+    /// A fixed timestep with `hz` updates per second and at most 5 updates per frame.
+    ///
+    /// # Panics
+    ///
+    /// If `hz` is 0.
+    pub fn fixed_hz(hz: u32) -> Timestep {
+        assert!(hz > 0, "a fixed timestep needs at least 1 update per second");
+        Timestep::Fixed {
+            step: Duration::from_secs(1) / hz,
+            max_steps: 5,
+        }
+    }
+}
+
+/// Measures frame times and decides how many fixed updates are due.
+pub(crate) struct Clock {
+    timestep: Timestep,
+    /// Pretend every frame takes this long instead of measuring it, for deterministic runs.
+    simulated: Option<Duration>,
+    /// Frame times of a recording, used for the next frames before `simulated` or measuring.
+    scripted: VecDeque<Duration>,
+    last: Option<Instant>,
+    frame: Time,
+    fixed: Time,
+    accumulator: Duration,
+}
+
+impl Clock {
+    pub(crate) fn new(timestep: Timestep, simulated: Option<Duration>) -> Clock {
+        if let Timestep::Fixed { step, .. } = timestep {
+            assert!(!step.is_zero(), "the step of a fixed timestep must not be zero");
+        }
+        Clock {
+            timestep,
+            simulated,
+            scripted: VecDeque::new(),
+            last: None,
+            frame: Time::default(),
+            fixed: Time::default(),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// The next frames take exactly these times (the first one included), so a recorded run
+    /// gets the same deltas and fixed updates when it is replayed.
+    pub(crate) fn script(&mut self, frame_times: &[Duration]) {
+        self.scripted = frame_times.iter().copied().collect();
+    }
+
+    /// Starts a new frame, returns the times of the fixed updates that are due and the time of
+    /// the frame. The first frame has a delta of 0.
+    pub(crate) fn tick(&mut self) -> (Vec<Time>, Time) {
+        let now = Instant::now();
+        let delta = match (self.scripted.pop_front(), self.simulated, self.last) {
+            (Some(d), _, _) => d,
+            (None, Some(d), Some(_)) => d,
+            (None, None, Some(last)) => now - last,
+            (None, _, None) => Duration::ZERO,
+        };
+        if self.last.is_some() {
+            self.frame.frame += 1;
+        }
+        self.last = Some(now);
+
+        self.frame.delta = delta;
+        self.frame.elapsed += delta;
+
+        let mut steps = vec![];
+        if let Timestep::Fixed { step, max_steps } = self.timestep {
+            self.accumulator += delta;
+            while self.accumulator >= step && steps.len() < max_steps as usize {
+                self.accumulator -= step;
+                self.fixed.delta = step;
+                self.fixed.elapsed += step;
+                steps.push(self.fixed);
+                self.fixed.frame += 1;
+            }
+            // Too far behind, drop the rest instead of trying to catch up forever.
+            if self.accumulator >= step {
+                self.accumulator = Duration::ZERO;
+            }
+            self.frame.alpha = self.accumulator.as_secs_f64() / step.as_secs_f64();
+        }
+
+        (steps, self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::time::{Clock, Timestep};
+
+    #[test]
+    fn test_fixed_timestep() {
+        let ms = Duration::from_millis;
+        let mut clock = Clock::new(Timestep::Fixed { step: ms(10), max_steps: 3 }, Some(ms(25)));
+
+        let (steps, time) = clock.tick();
+        assert!(steps.is_empty());
+        assert_eq!(time.delta, Duration::ZERO);
+
+        let (steps, time) = clock.tick();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].elapsed, ms(20));
+        assert_eq!(time.elapsed, ms(25));
+        assert_eq!(time.frame, 1);
+        assert!((time.alpha - 0.5).abs() < 1e-9);
+
+        let (steps, _) = clock.tick();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2].frame, 4);
+
+        // A long frame is clamped to `max_steps`.
+        let mut clock = Clock::new(Timestep::Fixed { step: ms(10), max_steps: 3 }, Some(ms(100)));
+        clock.tick();
+        assert_eq!(clock.tick().0.len(), 3);
+        assert_eq!(clock.tick().0.len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 update per second")]
+    fn test_fixed_hz_rejects_zero() {
+        Timestep::fixed_hz(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be zero")]
+    fn test_zero_step_is_rejected() {
+        Clock::new(Timestep::Fixed { step: Duration::ZERO, max_steps: 3 }, None);
+    }
+}